@@ -0,0 +1,62 @@
+// Microbenchmarks for the board operations every search spends its time in.
+// Run with cargo bench, the search as a whole is measured by the bench
+// command instead.
+#![allow(clippy::needless_return)]
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rust4::board;
+use rust4::board::Board;
+
+// A middlegame from the bench command's positions, with room in every column
+const POSITION: &str = "714245541";
+
+fn position() -> Board {
+    let mut position = board::from_move_string(POSITION, 7, 6).unwrap();
+    board::set_print(&mut position, false);
+    return position;
+}
+
+fn add_piece(c: &mut Criterion) {
+    let mut position = position();
+    let piece = board::next_piece(&position);
+    c.bench_function("add_piece", |b| {
+        b.iter(|| {
+            board::add_piece(&mut position, black_box(3), piece);
+            board::undo_move(&mut position);
+        })
+    });
+}
+
+fn game_over_check(c: &mut Criterion) {
+    let mut position = position();
+    let piece = board::next_piece(&position);
+    board::add_piece(&mut position, 3, piece);
+    c.bench_function("game_over_check", |b| {
+        b.iter(|| board::game_over_check(black_box(&mut position)))
+    });
+}
+
+fn clone_board(c: &mut Criterion) {
+    let position = position();
+    c.bench_function("clone_board", |b| {
+        b.iter(|| board::clone_board(black_box(&position)))
+    });
+}
+
+fn move_generation(c: &mut Criterion) {
+    let position = position();
+    c.bench_function("legal_moves", |b| {
+        b.iter(|| board::legal_moves(black_box(&position)))
+    });
+    c.bench_function("get_empty_columns", |b| {
+        b.iter(|| board::get_empty_columns(black_box(&position)))
+    });
+}
+
+criterion_group!(
+    benches,
+    add_piece,
+    game_over_check,
+    clone_board,
+    move_generation
+);
+criterion_main!(benches);
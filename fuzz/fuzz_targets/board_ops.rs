@@ -0,0 +1,120 @@
+// Drives a Board through any sequence of moves, undos and win checks. The
+// first three bytes pick the variant and the size, then every two bytes are
+// an operation and its argument. Nothing may panic, and after every
+// operation the board has to hold together: one piece on it for every move
+// still played (less the pops) and, with gravity, lowest_empty right above
+// the top piece of each column.
+#![no_main]
+#![allow(clippy::needless_return)]
+use libfuzzer_sys::fuzz_target;
+use rust4::board;
+use rust4::board::{Board, Move};
+
+// Bigger boards don't find anything smaller ones don't and are slower
+const MAX_SIZE: usize = 10;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 3 {
+        return;
+    }
+    // Nothing makes boards with no rows or columns
+    let width = 1 + data[1] as usize % MAX_SIZE;
+    let height = 1 + data[2] as usize % MAX_SIZE;
+    let mut board = match data[0] % 7 {
+        0 => board::new_board(width, height),
+        1 => board::new_popout_board(width, height),
+        2 => board::new_cylinder_board(width, height),
+        3 => board::new_misere_board(width, height),
+        4 => board::new_multiplayer_board(width, height, 3),
+        5 => board::new_mnk_board(width, height, 1 + data[2] as usize % 6),
+        6 => board::new_five_board(),
+        _ => board::new_board(width, height),
+    };
+    board::set_print(&mut board, false);
+    // The setup pieces of the five board count as played
+    let start_pieces = pieces(&board);
+    // +1 for every move on the board, -1 for pops, in the order played
+    let mut played: Vec<isize> = Vec::new();
+
+    for op in data[3..].chunks(2) {
+        let arg = *op.get(1).unwrap_or(&0) as usize;
+        let piece = board::next_piece(&board);
+        match op[0] % 6 {
+            0 => {
+                if board::add_piece(&mut board, arg % (MAX_SIZE + 1), piece) {
+                    played.push(1);
+                }
+            }
+            1 => {
+                if board::pop_piece(&mut board, arg % (MAX_SIZE + 1), piece) {
+                    played.push(-1);
+                }
+            }
+            2 => {
+                if board::place_piece(&mut board, arg / 16, arg % 16, piece) {
+                    played.push(1);
+                }
+            }
+            3 => {
+                board::undo_move(&mut board);
+                played.pop();
+            }
+            4 => {
+                board::game_over_check(&mut board);
+                board::winners(&board);
+            }
+            _ => {
+                // Every legal move has to be playable and undoable
+                for mv in board::legal_moves(&board) {
+                    assert!(
+                        board::make_move(&mut board, mv, piece),
+                        "{:?} is legal but can't be played",
+                        mv
+                    );
+                    assert!(!matches!(mv, Move::Pop(_)) || board::is_popout(&board));
+                    board::undo_move(&mut board);
+                }
+            }
+        }
+        check(&board, start_pieces as isize + played.iter().sum::<isize>());
+    }
+});
+
+fn pieces(board: &Board) -> usize {
+    return board::get_grid(board)
+        .iter()
+        .flatten()
+        .filter(|cell| **cell != 0)
+        .count();
+}
+
+fn check(board: &Board, expected_pieces: isize) {
+    assert_eq!(
+        pieces(board) as isize,
+        expected_pieces,
+        "pieces don't match the moves played"
+    );
+    if !board::has_gravity(board) {
+        return;
+    }
+    let grid = board::get_grid(board);
+    for col in 0..board::width(board) {
+        let lowest = board.lowest_empty[col];
+        assert!(
+            lowest <= grid.len(),
+            "lowest_empty off the board in column {}",
+            col
+        );
+        for (row, cells) in grid.iter().enumerate() {
+            let empty = cells[col] == 0;
+            assert_eq!(
+                empty,
+                row < lowest,
+                "lowest_empty is {} but row {} of column {} doesn't agree",
+                lowest,
+                row,
+                col
+            );
+        }
+    }
+}
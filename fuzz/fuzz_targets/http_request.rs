@@ -0,0 +1,21 @@
+// Raw HTTP requests to the JSON API (http.rs): the request parser, then the
+// routes for whatever it gets out. /bestmove only gets shallow alphabeta
+// searches, a deep one or a solve takes seconds and the fuzzer would get
+// nowhere. Bodies with escapes could spell "engine" some other way.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rust4::http;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok((method, path, body)) = http::read_request(data) else {
+        return;
+    };
+    if path == "/bestmove"
+        && (!body.contains("\"depth\":1}") || body.contains("engine") || body.contains('\\'))
+    {
+        return;
+    }
+    let (status, reply) = http::route(&method, &path, &body);
+    assert!([200, 400, 404, 405].contains(&status));
+    assert!(reply.starts_with('{'), "reply isn't JSON: {}", reply);
+});
@@ -0,0 +1,22 @@
+// Map files and the grids handicap and custom starting positions are set up
+// from (see board::from_map). A map that loads can't have a winner yet or a
+// piece floating in the air.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rust4::board;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(position) = board::from_map(text) {
+        assert!(board::winners(&position).is_empty());
+        let grid = board::get_grid(&position);
+        for (col, lowest) in position.lowest_empty.iter().enumerate() {
+            for cells in &grid[..*lowest] {
+                assert_eq!(cells[col], 0, "something above the top of column {}", col);
+            }
+        }
+        board::legal_moves(&position);
+    }
+});
@@ -0,0 +1,30 @@
+// Move strings, the way solve, analyze-batch, the HTTP API and the puzzles
+// read positions: the first two bytes are the board size, the rest the
+// string. Anything that parses has to have one piece per move on the board.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rust4::board;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 2 {
+        return;
+    }
+    let Ok(moves) = std::str::from_utf8(&data[2..]) else {
+        return;
+    };
+    let width = data[0] as usize % 11;
+    let height = data[1] as usize % 11;
+    if let Ok(position) = board::from_move_string(moves, width, height) {
+        let pieces = board::get_grid(&position)
+            .iter()
+            .flatten()
+            .filter(|cell| **cell != 0)
+            .count();
+        assert_eq!(pieces, moves.trim().chars().count());
+        assert!(
+            board::winners(&position).is_empty(),
+            "{} parsed but the game is over",
+            moves
+        );
+    }
+});
@@ -0,0 +1,16 @@
+// Game records (record.rs). Whatever parses has to come back the same after
+// being written out and read in again.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rust4::record;
+
+fuzz_target!(|data: &[u8]| {
+    let Ok(text) = std::str::from_utf8(data) else {
+        return;
+    };
+    if let Ok(parsed) = record::parse(text) {
+        let written = record::to_text(&parsed);
+        let again = record::parse(&written).expect("a written record doesn't parse");
+        assert_eq!(record::to_text(&again), written);
+    }
+});
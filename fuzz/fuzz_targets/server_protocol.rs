@@ -0,0 +1,50 @@
+// Lines from game server clients (server.rs). Two clients share a lobby and
+// each line goes to one of them, picked by its first byte, so they can sit
+// down at the same table and play each other. Bots are left out: they think
+// for far too long to fuzz.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use rust4::server;
+use std::sync::mpsc::channel;
+
+fuzz_target!(|data: &[u8]| {
+    let mut lobby = server::new_lobby();
+    let (outbox, inbox) = channel();
+    let clients = [
+        server::add_client(&mut lobby, outbox.clone()),
+        server::add_client(&mut lobby, outbox),
+    ];
+    for line in data.split(|byte| *byte == b'\n') {
+        let Some((first, line)) = line.split_first() else {
+            continue;
+        };
+        let Ok(line) = std::str::from_utf8(line) else {
+            continue;
+        };
+        let mut words = line.split_whitespace();
+        if words
+            .next()
+            .is_some_and(|word| word.eq_ignore_ascii_case("CREATE"))
+            && words.next().is_some()
+        {
+            continue;
+        }
+        let id = clients[*first as usize % clients.len()];
+        if !server::handle_command(&mut lobby, id, line) {
+            break;
+        }
+        // Every reply is one of the documented kinds of line
+        for reply in inbox.try_iter() {
+            let kind = reply.split_whitespace().next().unwrap_or("");
+            assert!(
+                ["OK", "ERROR", "TABLE", "START", "BOARD", "MOVED", "YOURTURN", "OVER"]
+                    .contains(&kind),
+                "unexpected reply: {}",
+                reply
+            );
+        }
+    }
+    for id in clients {
+        server::remove_client(&mut lobby, id);
+    }
+});
@@ -0,0 +1,145 @@
+// Analysis of a whole file of positions at once, one move string per line,
+// for benchmarking the solver against the standard test sets and labelling
+// positions. Anything after the move string on a line (test sets often have
+// the expected score there) is kept as is in the output.
+//
+// Positions are shared out between one thread per core. Every thread runs
+// its own searches, so the only thing they share is the solved positions
+// database, if one is open.
+use crate::board;
+use crate::search;
+use serde::Serialize;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+#[derive(Serialize)]
+pub struct Row {
+    // 1-based line in the input
+    pub line: usize,
+    pub moves: String,
+    // The rest of the input line
+    pub extra: String,
+    pub score: Option<i32>,
+    // "win in 5 plies", "loss in 8 plies", "draw" or an evaluation
+    pub result: String,
+    // Every 1-based column with the best score
+    pub best_moves: Vec<usize>,
+    pub nodes: u64,
+    pub time_ms: f64,
+    pub error: Option<String>,
+}
+
+// Analyzes every non-empty line, to the end of the game unless depth is
+// given. Rows come back in input order.
+pub fn analyze(text: &str, width: usize, height: usize, depth: Option<i32>) -> Vec<Row> {
+    let lines: Vec<(usize, &str)> = text
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(i, line)| (i + 1, line.trim()))
+        .collect();
+    let threads = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let next = AtomicUsize::new(0);
+    let rows: Mutex<Vec<Row>> = Mutex::new(Vec::with_capacity(lines.len()));
+
+    thread::scope(|scope| {
+        for _ in 0..threads.min(lines.len()) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some((line, text)) = lines.get(i) else {
+                    break;
+                };
+                let row = analyze_line(*line, text, width, height, depth);
+                rows.lock().unwrap().push(row);
+            });
+        }
+    });
+
+    let mut rows = rows.into_inner().unwrap();
+    rows.sort_by_key(|row| row.line);
+    return rows;
+}
+
+fn analyze_line(line: usize, text: &str, width: usize, height: usize, depth: Option<i32>) -> Row {
+    let (moves, extra) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+    let mut row = Row {
+        line,
+        moves: moves.to_string(),
+        extra: extra.trim().to_string(),
+        score: None,
+        result: String::new(),
+        best_moves: Vec::new(),
+        nodes: 0,
+        time_ms: 0.0,
+        error: None,
+    };
+    let position = match board::from_move_string(moves, width, height) {
+        Ok(position) => position,
+        Err(e) => {
+            row.error = Some(e);
+            return row;
+        }
+    };
+    if board::get_empty_columns(&position).is_empty() {
+        row.error = Some("board is full".to_string());
+        return row;
+    }
+
+    let start = Instant::now();
+    let result = match depth {
+        Some(depth) => search::search(&position, depth),
+        None => search::solve(&position),
+    };
+    row.time_ms = start.elapsed().as_secs_f64() * 1000.0;
+    row.score = Some(result.score);
+    row.result = if result.score == 0 && depth.is_none() {
+        "draw".to_string()
+    } else {
+        search::describe_score(result.score)
+    };
+    row.best_moves = result
+        .column_values
+        .iter()
+        .enumerate()
+        .filter(|(_, value)| **value == Some(result.score))
+        .map(|(column, _)| column + 1)
+        .collect();
+    row.nodes = result.stats.nodes;
+    return row;
+}
+
+pub fn to_csv(rows: &[Row]) -> String {
+    let mut out = String::from("line,moves,score,result,best_moves,nodes,time_ms,extra,error\n");
+    for row in rows {
+        let best: Vec<String> = row.best_moves.iter().map(|c| c.to_string()).collect();
+        out.push_str(&format!(
+            "{},{},{},{},{},{},{},{},{}\n",
+            csv_field(&row.line.to_string()),
+            csv_field(&row.moves),
+            csv_field(&row.score.map(|s| s.to_string()).unwrap_or_default()),
+            csv_field(&row.result),
+            csv_field(&best.join(" ")),
+            csv_field(&row.nodes.to_string()),
+            csv_field(&format!("{:.3}", row.time_ms)),
+            csv_field(&row.extra),
+            csv_field(row.error.as_deref().unwrap_or("")),
+        ));
+    }
+    return out;
+}
+
+pub fn to_json(rows: &[Row]) -> String {
+    return serde_json::to_string_pretty(rows).unwrap();
+}
+
+// Quotes a field if it has anything in it that CSV cares about
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        return format!("\"{}\"", text.replace('"', "\"\""));
+    }
+    return text.to_string();
+}
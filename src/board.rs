@@ -1,5 +1,33 @@
 // use rand::{random, seq::SliceRandom, thread_rng, Rng};
 use std::cmp::min;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::Hasher;
+
+// How many times a PopOut position has to come up for the game to be a draw
+pub const REPETITION_LIMIT: usize = 3;
+
+// How each player's pieces are drawn, player 1 first. There can't be more
+// players than symbols.
+pub const SYMBOLS: [char; 8] = ['X', 'O', '+', '=', '@', '%', '&', '$'];
+
+// Cells that aren't empty but don't belong to any player. A blocked cell
+// is a hole in the board, a neutral disc is a piece nobody owns. Pieces
+// land on top of both and no line can go through either.
+pub const BLOCKED: usize = usize::MAX;
+pub const NEUTRAL: usize = usize::MAX - 1;
+const BLOCKED_SYMBOL: char = '#';
+const NEUTRAL_SYMBOL: char = '*';
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Move {
+    // Drop a piece in the column, the normal Connect 4 move
+    Drop(usize),
+    // PopOut only: take one of your own pieces out of the bottom of the
+    // column, everything above it falls down one row
+    Pop(usize),
+    // Gravity-free boards only: put a piece on any empty cell (row, column)
+    Place(usize, usize),
+}
 
 pub struct Board {
     width: usize,
@@ -7,6 +35,30 @@ pub struct Board {
     grid: Vec<Vec<usize>>,
     pub lowest_empty: Vec<usize>,
     last_move: isize,
+    // Every move played so far and the piece that played it, so moves can
+    // be undone more than one deep
+    history: Vec<(Move, usize)>,
+    // How many in a row it takes to win
+    connect: usize,
+    // Cylinder rules: the left and right edges touch, so lines can wrap
+    wrap: bool,
+    // False for m,n,k-games (tic-tac-toe, gomoku...) where pieces stay
+    // wherever they are put. lowest_empty means nothing on those boards.
+    gravity: bool,
+    // Number of players taking turns, pieces are 1 to players
+    players: usize,
+    // Misère rules: whoever completes a line loses
+    misere: bool,
+    // Turns to skip ahead before the first move, so a set-up position can
+    // start with any player to move (see set_next_piece)
+    turn_offset: usize,
+    // Whether there are any BLOCKED or NEUTRAL cells on the board
+    obstacles: bool,
+    // PopOut rules: players may pop their own pieces off the bottom
+    popout: bool,
+    // PopOut only: the position_key (with the side to move) after every
+    // move, to spot repeated positions
+    seen: Vec<u64>,
     print: bool,
 }
 
@@ -14,13 +66,238 @@ pub fn new_board(width: usize, height: usize) -> Board {
     Board {
         width,
         // height,
-        grid: vec![vec![0; width as usize]; height as usize],
+        grid: vec![vec![0; width]; height],
         lowest_empty: vec![height; width],
         last_move: -1,
+        history: Vec::new(),
+        connect: 4,
+        wrap: false,
+        gravity: true,
+        players: 2,
+        misere: false,
+        turn_offset: 0,
+        obstacles: false,
+        popout: false,
+        seen: Vec::new(),
         print: true,
     }
 }
 
+pub fn new_popout_board(width: usize, height: usize) -> Board {
+    let mut board = new_board(width, height);
+    board.popout = true;
+    board.seen.push(repetition_key(&board));
+    return board;
+}
+
+// The commercial "5-in-a-Row" game: a 9x6 board where it takes five to
+// win, and the two outside columns start full of alternating pieces
+pub fn new_five_board() -> Board {
+    let mut board = new_board(9, 6);
+    board.connect = 5;
+    for i in 0..6 {
+        place_setup_piece(&mut board, 0, if i % 2 == 0 { 1 } else { 2 });
+        place_setup_piece(&mut board, 8, if i % 2 == 0 { 2 } else { 1 });
+    }
+    return board;
+}
+
+// Cylinder Connect 4: column 1 and the last column are next to each other,
+// so horizontal and diagonal lines can carry on around the edge
+pub fn new_cylinder_board(width: usize, height: usize) -> Board {
+    let mut board = new_board(width, height);
+    board.wrap = true;
+    return board;
+}
+
+// Anti-Connect 4: the board and moves are the normal ones, but the player
+// who completes four in a row loses
+pub fn new_misere_board(width: usize, height: usize) -> Board {
+    let mut board = new_board(width, height);
+    board.misere = true;
+    return board;
+}
+
+// Normal Connect 4 rules with more than two players taking turns, e.g. 3
+// players on a 9x7 board
+pub fn new_multiplayer_board(width: usize, height: usize, players: usize) -> Board {
+    let mut board = new_board(width, height);
+    board.players = players.clamp(2, SYMBOLS.len());
+    return board;
+}
+
+// Generalized m,n,k-game: any empty cell can be played and it takes
+// `connect` in a row to win, e.g. 3x3 with 3 is tic-tac-toe and 15x15 with
+// 5 is gomoku
+pub fn new_mnk_board(width: usize, height: usize, connect: usize) -> Board {
+    let mut board = new_board(width, height);
+    board.gravity = false;
+    board.connect = connect;
+    return board;
+}
+
+// Drops a piece that is part of the starting position rather than a move,
+// so it doesn't go in the history and can't be undone
+pub fn place_setup_piece(board: &mut Board, col: usize, piece: usize) -> bool {
+    if col >= board.width || board.lowest_empty[col] == 0 {
+        return false;
+    }
+    board.grid[board.lowest_empty[col] - 1][col] = piece;
+    board.lowest_empty[col] -= 1;
+    return true;
+}
+
+// Handicap: an extra piece for the weaker side in the center column,
+// before the game starts. Whoever is to move afterwards is up to the caller
+// (see infer_next_piece).
+pub fn add_handicap(board: &mut Board, piece: usize) -> bool {
+    if !board.gravity || piece == 0 || piece > board.players || !board.history.is_empty() {
+        return false;
+    }
+    let placed = place_setup_piece(board, (board.width - 1) / 2, piece);
+    reset_repetitions(board);
+    return placed;
+}
+
+// Sets up the starting position from rows of cells, top row first,
+// separated by '/' (or new lines), using '.' for empty cells and
+// SYMBOLS for pieces, e.g. "......./......./......./......./...O.../...X..."
+// '#' is a BLOCKED cell and '*' a NEUTRAL disc.
+// Replaces whatever the variant started with. The side to move is whoever
+// has the fewest pieces, use set_next_piece to pick someone else.
+pub fn setup_from_grid(board: &mut Board, rows: &str) -> Result<(), String> {
+    if !board.history.is_empty() {
+        return Err("moves have already been played".to_string());
+    }
+    let rows: Vec<&str> = rows
+        .split(['/', '\n'])
+        .map(|r| r.trim())
+        .filter(|r| !r.is_empty())
+        .collect();
+    let height = board.grid.len();
+    if rows.len() != height {
+        return Err(format!("expected {} rows, got {}", height, rows.len()));
+    }
+    let mut grid = vec![vec![0; board.width]; height];
+    for (row, cells) in rows.iter().enumerate() {
+        let cells: Vec<char> = cells.chars().collect();
+        if cells.len() != board.width {
+            return Err(format!("row {} should have {} cells", row + 1, board.width));
+        }
+        for (col, cell) in cells.iter().enumerate() {
+            grid[row][col] = match SYMBOLS[..board.players].iter().position(|s| s == cell) {
+                Some(i) => i + 1,
+                None if *cell == '.' => 0,
+                None if *cell == BLOCKED_SYMBOL => BLOCKED,
+                None if *cell == NEUTRAL_SYMBOL => NEUTRAL,
+                None => return Err(format!("unknown piece '{}'", cell)),
+            };
+        }
+    }
+    let obstacles = grid
+        .iter()
+        .flatten()
+        .any(|cell| *cell == BLOCKED || *cell == NEUTRAL);
+    // Popping would move obstacles around
+    if obstacles && board.popout {
+        return Err("PopOut boards can't have obstacles".to_string());
+    }
+
+    // lowest_empty ends up on the highest filled cell of each column, so
+    // a blocked cell with empty cells under it closes those cells off
+    let mut lowest_empty = vec![height; board.width];
+    for col in 0..board.width {
+        for row in (0..height).rev() {
+            if grid[row][col] == 0 {
+                continue;
+            }
+            // With gravity every piece has to sit on top of another one,
+            // or on a blocked cell. Blocked cells can be anywhere.
+            if board.gravity && grid[row][col] != BLOCKED && lowest_empty[col] != row + 1 {
+                return Err(format!("piece floating in column {}", col + 1));
+            }
+            lowest_empty[col] = row;
+        }
+    }
+    board.grid = grid;
+    board.lowest_empty = lowest_empty;
+    board.obstacles = obstacles;
+    if !winners(board).is_empty() {
+        return Err("game is already over".to_string());
+    }
+    infer_next_piece(board);
+    return Ok(());
+}
+
+// Reads a board layout from a map file: optional "connect <n>" and
+// "players <n>" lines, then the rows as setup_from_grid takes them, one
+// per line. The board is as wide and tall as the rows. Lines starting with
+// ';' are comments.
+pub fn from_map(text: &str) -> Result<Board, String> {
+    let mut connect = 4;
+    let mut players = 2;
+    let mut rows = Vec::new();
+    for line in text.lines().map(|l| l.trim()) {
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words[..] {
+            ["connect", n] => connect = n.parse().map_err(|_| format!("invalid connect: {}", n))?,
+            ["players", n] => players = n.parse().map_err(|_| format!("invalid players: {}", n))?,
+            _ => rows.push(line),
+        }
+    }
+    if rows.is_empty() {
+        return Err("map has no rows".to_string());
+    }
+    let width = rows[0].chars().count();
+    if width == 0 || connect < 2 {
+        return Err("map is too small".to_string());
+    }
+    let mut board = new_multiplayer_board(width, rows.len(), players);
+    board.connect = connect;
+    setup_from_grid(&mut board, &rows.join("/"))?;
+    return Ok(board);
+}
+
+// True for cells that hold one of the players' pieces, as opposed to
+// empty, blocked and neutral cells
+pub fn is_piece(board: &Board, cell: usize) -> bool {
+    return cell >= 1 && cell <= board.players;
+}
+
+// Gives the move to whoever has the fewest pieces on the board, the
+// earliest in turn order if that's a tie
+pub fn infer_next_piece(board: &mut Board) {
+    let mut counts = vec![0; board.players];
+    for cell in board.grid.iter().flatten() {
+        if is_piece(board, *cell) {
+            counts[cell - 1] += 1;
+        }
+    }
+    let fewest = (0..board.players).min_by_key(|i| counts[*i]).unwrap();
+    set_next_piece(board, fewest + 1);
+}
+
+// Makes it piece's turn now. Turns carry on in the normal order from there.
+pub fn set_next_piece(board: &mut Board, piece: usize) -> bool {
+    if piece == 0 || piece > board.players {
+        return false;
+    }
+    let played = board.history.len() % board.players;
+    board.turn_offset = (piece - 1 + board.players - played) % board.players;
+    reset_repetitions(board);
+    return true;
+}
+
+// The set-up position is the first one a PopOut game can repeat
+fn reset_repetitions(board: &mut Board) {
+    if board.popout && board.history.is_empty() {
+        board.seen = vec![repetition_key(board)];
+    }
+}
+
 pub fn clone_board(board: &Board) -> Board {
     Board {
         width: board.width,
@@ -28,8 +305,491 @@ pub fn clone_board(board: &Board) -> Board {
         grid: board.grid.clone(),
         lowest_empty: board.lowest_empty.clone(),
         last_move: board.last_move,
-        print: true,
+        history: board.history.clone(),
+        connect: board.connect,
+        wrap: board.wrap,
+        gravity: board.gravity,
+        players: board.players,
+        misere: board.misere,
+        turn_offset: board.turn_offset,
+        obstacles: board.obstacles,
+        popout: board.popout,
+        seen: board.seen.clone(),
+        print: board.print,
+    }
+}
+
+pub fn is_popout(board: &Board) -> bool {
+    return board.popout;
+}
+
+pub fn is_misere(board: &Board) -> bool {
+    return board.misere;
+}
+
+// Solved values in the database and the opening book are only right for
+// plain Connect 4: two players, four in a row, gravity without popping,
+// normal edges and normal winning rules, with whoever would be to move in a
+// normal game with this many pieces on the board (a handicap position with
+// the same pieces but the other side to move isn't worth the same)
+pub fn standard_scoring(board: &Board) -> bool {
+    let pieces = board.grid.len() * board.width - empty_cells(board);
+    return board.players == 2
+        && board.connect == 4
+        && board.gravity
+        && !board.popout
+        && !board.wrap
+        && !board.misere
+        && !board.obstacles
+        && next_piece(board) == pieces % 2 + 1;
+}
+
+pub fn players(board: &Board) -> usize {
+    return board.players;
+}
+
+// Game result for a draw: one past the last player's piece, so 3 in a
+// normal two player game
+pub fn tie_result(board: &Board) -> usize {
+    return board.players + 1;
+}
+
+// The player whose turn comes after piece's
+pub fn next_player(board: &Board, piece: usize) -> usize {
+    return piece % board.players + 1;
+}
+
+// Everyone but piece, in the order they play after it
+pub fn other_pieces(board: &Board, piece: usize) -> Vec<usize> {
+    let mut others = Vec::new();
+    let mut other = next_player(board, piece);
+    while other != piece {
+        others.push(other);
+        other = next_player(board, other);
+    }
+    return others;
+}
+
+pub fn has_gravity(board: &Board) -> bool {
+    return board.gravity;
+}
+
+// Whether there are any BLOCKED or NEUTRAL cells on the board
+pub fn has_obstacles(board: &Board) -> bool {
+    return board.obstacles;
+}
+
+pub fn connect_length(board: &Board) -> usize {
+    return board.connect;
+}
+
+pub fn width(board: &Board) -> usize {
+    return board.width;
+}
+
+// Turns the "WIN" debug lines in game_over_check on or off. Searches check
+// for wins constantly and should switch them off on their copy of the board.
+pub fn set_print(board: &mut Board, print: bool) {
+    board.print = print;
+}
+
+// Builds a board from a string of 1-based columns, e.g. "4453" means X
+// played column 4, O played 4, X played 5 and O played 3
+pub fn from_move_string(moves: &str, width: usize, height: usize) -> Result<Board, String> {
+    let mut board = new_board(width, height);
+    apply_moves(&mut board, moves)?;
+    return Ok(board);
+}
+
+// Plays a move string (see from_move_string) on any board with gravity,
+// with whoever is to move going first. The moves stay in the history, so
+// they can be undone.
+pub fn apply_moves(board: &mut Board, moves: &str) -> Result<(), String> {
+    // Keep game_over_check quiet while replaying
+    let print = board.print;
+    board.print = false;
+    let result = replay_moves(board, moves);
+    board.print = print;
+    return result;
+}
+
+fn replay_moves(board: &mut Board, moves: &str) -> Result<(), String> {
+    for (turn, c) in (1..).zip(moves.trim().chars()) {
+        let column = match c.to_digit(10) {
+            Some(d) if d > 0 && (d as usize) <= board.width => d as usize - 1,
+            _ => return Err(format!("invalid column '{}' in move string", c)),
+        };
+        let piece = next_piece(board);
+        if !add_piece(board, column, piece) {
+            return Err(format!("column {} is full", column + 1));
+        }
+        if game_over_check(board) {
+            return Err(format!("game is already over after move {}", turn));
+        }
+    }
+    return Ok(());
+}
+
+// Figures out whose turn it is from the moves played so far. Counting
+// pieces doesn't work once PopOut takes pieces off the board.
+pub fn next_piece(board: &Board) -> usize {
+    return (board.history.len() + board.turn_offset) % board.players + 1;
+}
+
+// The piece that made the last move, 0 if nothing has been played
+pub fn last_piece(board: &Board) -> usize {
+    match board.history.last() {
+        Some((_, piece)) => return *piece,
+        None => return 0,
+    }
+}
+
+// Every move the side to move could make: drops into columns that aren't
+// full and, in PopOut, pops of their own pieces from the bottom row. Without
+// gravity it's every empty cell instead.
+pub fn legal_moves(board: &Board) -> Vec<Move> {
+    if !board.gravity {
+        let mut moves = Vec::new();
+        for (row, cells) in board.grid.iter().enumerate() {
+            for (col, cell) in cells.iter().enumerate() {
+                if *cell == 0 {
+                    moves.push(Move::Place(row, col));
+                }
+            }
+        }
+        return moves;
+    }
+    let mut moves: Vec<Move> = get_empty_columns(board)
+        .into_iter()
+        .map(Move::Drop)
+        .collect();
+    if board.popout {
+        let piece = next_piece(board);
+        let bottom = board.grid.len() - 1;
+        for col in 0..board.width {
+            if board.grid[bottom][col] == piece {
+                moves.push(Move::Pop(col));
+            }
+        }
     }
+    return moves;
+}
+
+pub fn make_move(board: &mut Board, player_move: Move, piece: usize) -> bool {
+    match player_move {
+        Move::Drop(col) => return add_piece(board, col, piece),
+        Move::Pop(col) => return pop_piece(board, col, piece),
+        Move::Place(row, col) => return place_piece(board, row, col, piece),
+    }
+}
+
+// Puts a piece on an empty cell of a gravity-free board
+pub fn place_piece(board: &mut Board, row: usize, col: usize, piece: usize) -> bool {
+    if board.gravity || row >= board.grid.len() || col >= board.width || board.grid[row][col] != 0 {
+        return false;
+    }
+    board.grid[row][col] = piece;
+    board.last_move = col as isize;
+    board.history.push((Move::Place(row, col), piece));
+    return true;
+}
+
+// Removes the piece at the bottom of a column if it belongs to the player,
+// and lets everything above it fall down a row
+pub fn pop_piece(board: &mut Board, col: usize, piece: usize) -> bool {
+    let bottom = board.grid.len() - 1;
+    if !board.popout || col >= board.width || board.grid[bottom][col] != piece {
+        return false;
+    }
+    let top = board.lowest_empty[col];
+    for row in (top + 1..=bottom).rev() {
+        board.grid[row][col] = board.grid[row - 1][col];
+    }
+    board.grid[top][col] = 0;
+    board.lowest_empty[col] += 1;
+    board.last_move = col as isize;
+    board.history.push((Move::Pop(col), piece));
+    board.seen.push(repetition_key(board));
+    return true;
+}
+
+// Key for spotting repeated positions. Unlike plain Connect 4 the same
+// pieces can be on the board with either player to move, so that goes in
+// too, above the bits position keys use.
+fn repetition_key(board: &Board) -> u64 {
+    if exact_keys(board) && board.width * (board.grid.len() + 1) <= 60 {
+        return pack_columns(board).0 | ((next_piece(board) as u64) << 60);
+    }
+    return hash_cells(board, false, next_piece(board));
+}
+
+// How many times the current position has come up in a PopOut game
+pub fn repetition_count(board: &Board) -> usize {
+    if board.seen.is_empty() {
+        return 0;
+    }
+    let current = board.seen[board.seen.len() - 1];
+    return board.seen.iter().filter(|key| **key == current).count();
+}
+
+// Looks up a cell that may be off the board. Off the top or bottom is
+// always None, off the sides wraps around on a cylinder.
+fn cell_at(board: &Board, row: isize, col: isize) -> Option<usize> {
+    if row < 0 || row >= board.grid.len() as isize {
+        return None;
+    }
+    let width = board.width as isize;
+    let col = if board.wrap {
+        col.rem_euclid(width)
+    } else if col < 0 || col >= width {
+        return None;
+    } else {
+        col
+    };
+    return Some(board.grid[row as usize][col as usize]);
+}
+
+// Calls visit with the cells of every line of connect_length cells on the
+// board, as (row, column) pairs, whatever is in them. On a cylinder lines
+// carry on around the side.
+pub fn for_each_line(board: &Board, mut visit: impl FnMut(&[(usize, usize)])) {
+    let height = board.grid.len() as isize;
+    let width = board.width as isize;
+    let connect = board.connect as isize;
+    let mut stack = [(0, 0); STACK_LINE];
+    let mut heap = Vec::new();
+    let cells = line_buffer(&mut stack, &mut heap, board.connect);
+
+    // right, down, down-right, up-right
+    let directions = [(0, 1), (1, 0), (1, 1), (-1, 1)];
+    for row in 0..height {
+        for column in 0..width {
+            for (dr, dc) in directions.iter() {
+                let end_row = row + dr * (connect - 1);
+                let end_column = column + dc * (connect - 1);
+                if end_row < 0
+                    || end_row >= height
+                    || (end_column >= width && !board.wrap)
+                    || !fits_in_row(board, *dr)
+                {
+                    continue;
+                }
+                for (i, cell) in cells.iter_mut().enumerate() {
+                    let i = i as isize;
+                    *cell = (
+                        (row + dr * i) as usize,
+                        ((column + dc * i) % width) as usize,
+                    );
+                }
+                visit(cells);
+            }
+        }
+    }
+}
+
+// Lines up to this long are gathered on the stack, so walking them doesn't
+// allocate. Only maps asking for longer lines need the heap.
+pub const STACK_LINE: usize = 8;
+
+// Room for a line of len cells: the start of stack if it fits, heap
+// otherwise
+pub fn line_buffer<'a, T: Copy + Default>(
+    stack: &'a mut [T; STACK_LINE],
+    heap: &'a mut Vec<T>,
+    len: usize,
+) -> &'a mut [T] {
+    if len <= STACK_LINE {
+        return &mut stack[..len];
+    }
+    heap.resize(len, T::default());
+    return heap;
+}
+
+// Whether piece would have a line through an empty cell if it had a piece
+// there, without playing it
+pub fn completes_line(board: &Board, row: usize, col: usize, piece: usize) -> bool {
+    return line_through(board, row, col, piece);
+}
+
+// Checks if the piece in a cell is part of a winning line in any direction
+pub fn in_line(board: &Board, row: usize, col: usize) -> bool {
+    let piece = board.grid[row][col];
+    if !is_piece(board, piece) {
+        return false;
+    }
+    return line_through(board, row, col, piece);
+}
+
+// Whether the cells on both sides of (row, col) in some direction, along
+// with the cell itself, make a line of piece. The cell itself isn't looked
+// at.
+fn line_through(board: &Board, row: usize, col: usize, piece: usize) -> bool {
+    let reach = board.connect as isize - 1;
+    let directions = [(0, 1), (1, 0), (1, 1), (-1, 1)];
+    for (dr, dc) in directions.iter() {
+        if !fits_in_row(board, *dr) {
+            continue;
+        }
+        // Count matching pieces on both sides of the cell. Never look
+        // further than a line's length, or a full row on a cylinder would
+        // go round forever.
+        let mut count = 1;
+        for sign in [1, -1] {
+            for i in 1..=reach {
+                let r = row as isize + dr * sign * i;
+                let c = col as isize + dc * sign * i;
+                if cell_at(board, r, c) != Some(piece) {
+                    break;
+                }
+                count += 1;
+            }
+        }
+        if count >= board.connect {
+            return true;
+        }
+    }
+    return false;
+}
+
+// Whether a line going in direction dr (0 along a row) can be made at all.
+// A cylinder narrower than a line would come back round onto the cells it
+// already went through, so no line fits along its rows.
+fn fits_in_row(board: &Board, dr: isize) -> bool {
+    return dr != 0 || board.connect <= board.width;
+}
+
+// Looks at the whole board rather than just the last move and returns every
+// piece that has a winning line somewhere. After a pop both players can
+// connect at the same time.
+pub fn winners(board: &Board) -> Vec<usize> {
+    let height = board.grid.len() as isize;
+    let width = board.width as isize;
+    let reach = board.connect as isize - 1;
+    let mut found = Vec::new();
+    // right, down, down-right, up-right
+    let directions = [(0, 1), (1, 0), (1, 1), (-1, 1)];
+    for row in 0..height {
+        for col in 0..width {
+            let piece = board.grid[row as usize][col as usize];
+            if !is_piece(board, piece) || found.contains(&piece) {
+                continue;
+            }
+            for (dr, dc) in directions.iter() {
+                if fits_in_row(board, *dr)
+                    && (1..=reach)
+                        .all(|i| cell_at(board, row + dr * i, col + dc * i) == Some(piece))
+                {
+                    found.push(piece);
+                    break;
+                }
+            }
+        }
+    }
+    return found;
+}
+
+// A position and its mirror image are worth the same, so anything that
+// stores positions keys them by whichever of the two keys is smaller.
+// Returns that key and true if it was the mirror image's key, in which
+// case stored moves need to go through mirror_column.
+pub fn canonical_key(board: &Board) -> (u64, bool) {
+    let (key, mirrored) = if exact_keys(board) {
+        pack_columns(board)
+    } else if symmetric_obstacles(board) {
+        (hash_cells(board, false, 0), hash_cells(board, true, 0))
+    } else {
+        // The mirror image isn't a position on this board at all
+        let key = hash_cells(board, false, 0);
+        (key, key)
+    };
+    if mirrored < key {
+        return (mirrored, true);
+    }
+    return (key, false);
+}
+
+// The column that lines up with col when the board is flipped left to right
+pub fn mirror_column(board: &Board, col: usize) -> usize {
+    return board.width - 1 - col;
+}
+
+// Whether canonical_key packs the position exactly, see pack_columns.
+// Other boards get a hash of every cell, which can in principle collide, so
+// anything that keeps positions around for good (the database and the
+// opening book) only takes boards with exact keys.
+pub fn exact_keys(board: &Board) -> bool {
+    return board.gravity
+        && board.players == 2
+        && !board.obstacles
+        && board.width * (board.grid.len() + 1) <= 64;
+}
+
+// Whether the BLOCKED cells look the same flipped left to right
+fn symmetric_obstacles(board: &Board) -> bool {
+    return board.grid.iter().all(|cells| {
+        (0..board.width)
+            .all(|col| (cells[col] == BLOCKED) == (cells[mirror_column(board, col)] == BLOCKED))
+    });
+}
+
+// Hashes every cell, obstacles included, column by column (right to left
+// if mirrored) and then extra
+fn hash_cells(board: &Board, mirrored: bool, extra: usize) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for i in 0..board.width {
+        let col = if mirrored { mirror_column(board, i) } else { i };
+        for cells in board.grid.iter() {
+            hasher.write_usize(cells[col]);
+        }
+    }
+    hasher.write_usize(extra);
+    return hasher.finish();
+}
+
+// Packs the position into a number that is different for every position.
+// Each column gets height + 1 bits: one per piece from the bottom up (1 for
+// X, 0 for O) and then a single 1 above the top piece to mark where the
+// column ends. Only works for boards that pass exact_keys: two players,
+// gravity, no obstacles and width * (height + 1) fitting in 64 bits, which
+// the standard 7x6 board does with room to spare.
+//
+// The key and the mirrored key are built together, the columns are the
+// same bits either way, just shifted into opposite slots
+fn pack_columns(board: &Board) -> (u64, u64) {
+    let height = board.grid.len();
+    let mut key: u64 = 0;
+    let mut mirrored: u64 = 0;
+    for col in 0..board.width {
+        let mut bits: u64 = 0;
+        let mut pieces = 0;
+        for row in (board.lowest_empty[col]..height).rev() {
+            if board.grid[row][col] == 1 {
+                bits |= 1 << pieces;
+            }
+            pieces += 1;
+        }
+        bits |= 1 << pieces;
+        key |= bits << (col * (height + 1));
+        mirrored |= bits << ((board.width - 1 - col) * (height + 1));
+    }
+    return (key, mirrored);
+}
+
+// Number of cells that can still be played
+pub fn empty_cells(board: &Board) -> usize {
+    if !board.gravity {
+        return board
+            .grid
+            .iter()
+            .flatten()
+            .filter(|cell| **cell == 0)
+            .count();
+    }
+    return board.lowest_empty.iter().sum();
+}
+
+pub fn get_grid(board: &Board) -> &Vec<Vec<usize>> {
+    return &board.grid;
 }
 
 pub fn get_empty_columns(board: &Board) -> Vec<usize> {
@@ -43,11 +803,38 @@ pub fn get_empty_columns(board: &Board) -> Vec<usize> {
 }
 
 pub fn undo_move(board: &mut Board) {
-    if board.last_move != -1 {
-        board.grid[board.lowest_empty[board.last_move as usize]][board.last_move as usize] = 0;
-        board.lowest_empty[board.last_move as usize] += 1;
-        board.last_move = -1;
+    let (last, piece) = match board.history.pop() {
+        Some(last) => last,
+        None => return,
+    };
+    match last {
+        Move::Drop(column) => {
+            board.grid[board.lowest_empty[column]][column] = 0;
+            board.lowest_empty[column] += 1;
+        }
+        Move::Pop(column) => {
+            // Push the column back up and put the popped piece back under it
+            let bottom = board.grid.len() - 1;
+            board.lowest_empty[column] -= 1;
+            for row in board.lowest_empty[column]..bottom {
+                board.grid[row][column] = board.grid[row + 1][column];
+            }
+            board.grid[bottom][column] = piece;
+        }
+        Move::Place(row, column) => {
+            board.grid[row][column] = 0;
+        }
+    }
+    if board.popout {
+        board.seen.pop();
     }
+    // The move before this one becomes the last move again
+    board.last_move = match board.history.last() {
+        Some((Move::Drop(previous), _))
+        | Some((Move::Pop(previous), _))
+        | Some((Move::Place(_, previous), _)) => *previous as isize,
+        None => -1,
+    };
 }
 
 // pub fn make_move(player_move: usize, player_piece: usize, board: &mut Board) -> bool {
@@ -59,8 +846,9 @@ pub fn undo_move(board: &mut Board) {
 // }
 
 pub fn add_piece(board: &mut Board, col: usize, piece: usize) -> bool {
-    // If column is out of boudns return false
-    if col > board.width - 1 {
+    // If column is out of boudns (or there are no columns to drop into)
+    // return false
+    if col >= board.width || !board.gravity {
         return false;
     }
     // If column is full return false
@@ -71,8 +859,12 @@ pub fn add_piece(board: &mut Board, col: usize, piece: usize) -> bool {
     board.grid[board.lowest_empty[col] - 1][col] = piece;
     // Save the last move
     board.last_move = col as isize;
+    board.history.push((Move::Drop(col), piece));
     // Increment lowest empty
     board.lowest_empty[col] -= 1;
+    if board.popout {
+        board.seen.push(repetition_key(board));
+    }
     return true;
 }
 
@@ -85,30 +877,48 @@ pub fn get_player_piece(turn: i32) -> usize {
     }
 }
 
-pub fn print_board(board: &mut Board) {
-    // 0 = empty, 1 = red/X, 2 = yellow/O
-    for i in 0..6 {
-        for j in 0..7 {
-            match board.grid[i][j] {
-                0 => print!("  ."),
-                1 => print!("  X"),
-                2 => print!("  O"),
-                _ => print!("  ?"),
-                // 0 => print!("  0"),
-                // 1 => print!("  1"),
-                // 2 => print!("  2"),
-                // _ => print!("  ?"),
+// Renders the board for the terminal, a network client or anywhere else
+pub fn board_to_string(board: &Board) -> String {
+    let mut out = String::new();
+    // 0 = empty, 1 = red/X, 2 = yellow/O, then SYMBOLS for any more players
+    for (row_number, row) in board.grid.iter().enumerate() {
+        for cell in row.iter() {
+            match cell {
+                0 => out.push_str("  ."),
+                &BLOCKED => out.push_str(&format!("  {}", BLOCKED_SYMBOL)),
+                &NEUTRAL => out.push_str(&format!("  {}", NEUTRAL_SYMBOL)),
+                piece if *piece <= SYMBOLS.len() => {
+                    out.push_str(&format!("  {}", SYMBOLS[piece - 1]))
+                }
+                _ => out.push_str("  ?"),
             }
         }
-        println!();
+        // Gravity-free moves need a row too
+        if !board.gravity {
+            out.push_str(&format!("  {}", row_number + 1));
+        }
+        out.push('\n');
     }
-    for i in 0..7 {
-        print!("  {}", i + 1);
+    for i in 0..board.width {
+        out.push_str(&format!("{:>3}", i + 1));
     }
-    println!();
+    out.push('\n');
+    return out;
 }
 
 pub fn game_over_check(board: &mut Board) -> bool {
+    // Without gravity the row can't be worked out from the column, so take
+    // the whole move from the history
+    if !board.gravity {
+        if let Some((Move::Place(row, col), _)) = board.history.last().copied() {
+            return in_line(board, row, col);
+        }
+        return false;
+    }
+    // Nothing played yet, or everything undone
+    if board.last_move < 0 {
+        return false;
+    }
     let column = board.last_move as usize;
     // range of column is 0-6
     // range of row is 0-5
@@ -119,7 +929,7 @@ pub fn game_over_check(board: &mut Board) -> bool {
     // 6 = empty column, 0 = full column
     let row: isize = board.lowest_empty[column] as isize;
     // If column is empty return false, there can be no possible win
-    if row == 6 {
+    if row == board.grid.len() as isize {
         return false;
     }
 
@@ -129,27 +939,33 @@ pub fn game_over_check(board: &mut Board) -> bool {
     let color: usize = board.grid[row as usize][column];
     // print!("color: {} \n", color);
 
+    // On a cylinder lines can wrap around the edges, which the walks below
+    // stop at, so use the general line check instead
+    if board.wrap {
+        return in_line(board, row as usize, column);
+    }
+
     //println!("Game check: row: {row}, column: {column}, color: {color}");
 
     // ------------------
     // ----HORIZONTAL----
     // ------------------
 
-    // Just keep going left in this row until you get win_counter to 3
+    // Just keep going left in this row until you get win_counter to connect - 1
     // or you run into the opposite color
     // keeping track of the last column there was a correct color piece,
     // you go back the other way and check if there are enough to the left
     // to make a 4 in a row
     // start one offset to the right because we know the first one is correct
-    for i in (column + 1)..board.grid[row as usize].len() as usize {
-        if board.grid[row as usize][i as usize] == color {
+    for i in (column + 1)..board.grid[row as usize].len() {
+        if board.grid[row as usize][i] == color {
             // println!("HORIZONTAL: {row}, {i}, {color}");
-            win_counter = win_counter + 1;
+            win_counter += 1;
         //if its not the same color, break we move to looking to the left
         } else {
             break;
         }
-        if win_counter == 3 {
+        if win_counter == board.connect - 1 {
             if board.print {
                 println!("HORIZONTAL WIN1");
             }
@@ -160,15 +976,14 @@ pub fn game_over_check(board: &mut Board) -> bool {
     //look to the left starting from the original column
     if column > 0 {
         for i in (0..column).rev() {
-            if board.grid[row as usize][i as usize] == color {
-                let l = board.grid[row as usize][i as usize];
-                // println!("HR: row: {row}, i: {i}, color: {color}, l: {l}");
-                win_counter = win_counter + 1;
+            if board.grid[row as usize][i] == color {
+                // println!("HR: row: {row}, i: {i}, color: {color}");
+                win_counter += 1;
             } else {
                 break;
             }
 
-            if win_counter == 3 {
+            if win_counter == board.connect - 1 {
                 if board.print {
                     println!("HORIZONTAL WIN2");
                 }
@@ -184,22 +999,22 @@ pub fn game_over_check(board: &mut Board) -> bool {
 
     win_counter = 0;
 
-    // You cant win vertical if there isn't already at least 3 pieces underneath
-    // so we dont check those
-    if row < 3 {
+    // You cant win vertical if there isn't already at least connect - 1 pieces
+    // underneath so we dont check those
+    if row as usize + board.connect <= board.grid.len() {
         let mut row_vertical: isize = row + 1;
         // //println!("VERTICAL: row_vertical: {row_vertical}");
 
         while row_vertical < board.grid.len() as isize {
-            if board.grid[row_vertical as usize][column as usize] == color {
+            if board.grid[row_vertical as usize][column] == color {
                 // //println!("VERTICAL PIECE: {row_vertical}, {column}, {color}");
-                win_counter = win_counter + 1;
+                win_counter += 1;
             } else {
                 break;
             }
-            row_vertical = row_vertical + 1;
+            row_vertical += 1;
 
-            if win_counter == 3 {
+            if win_counter == board.connect - 1 {
                 if board.print {
                     println!("VERTICAL WIN");
                 }
@@ -230,13 +1045,13 @@ pub fn game_over_check(board: &mut Board) -> bool {
     // let furthest_distance_dr = min(HEIGHT - row, WIDTH - column);
     //println!("furthest_distance: {furthest_distance_dr}");
     for i in 1..furthest_distance_dr {
-        if board.grid[(row + i) as usize][(column + i as usize) as usize] == color {
+        if board.grid[(row + i) as usize][column + i as usize] == color {
             //println!("DIAGONAL: {row}, {column}, {color}");
-            win_counter = win_counter + 1;
+            win_counter += 1;
         } else {
             break;
         }
-        if win_counter == 3 {
+        if win_counter == board.connect - 1 {
             if board.print {
                 println!("DIAGONAL WIN1");
             }
@@ -250,16 +1065,16 @@ pub fn game_over_check(board: &mut Board) -> bool {
     //     x
     //       x
     //         X
-    let furthest_distance_ul = min(row, column as isize);
+    let furthest_distance_ul = min(row, column as isize) + 1;
     //println!("furthest_distance: {furthest_distance_ul}");
     for i in 1..furthest_distance_ul {
-        if board.grid[(row - i) as usize][(column - i as usize) as usize] == color {
+        if board.grid[(row - i) as usize][column - i as usize] == color {
             //println!("DIAGONAL: {row}, {column}, {color}");
-            win_counter = win_counter + 1;
+            win_counter += 1;
         } else {
             break;
         }
-        if win_counter == 3 {
+        if win_counter == board.connect - 1 {
             if board.print {
                 println!("DIAGONAL WIN2");
             }
@@ -275,16 +1090,16 @@ pub fn game_over_check(board: &mut Board) -> bool {
     // X
 
     win_counter = 0;
-    let furthest_distance_dl = min(board.grid.len() as usize - row as usize - 1, column) + 1;
+    let furthest_distance_dl = min(board.grid.len() - row as usize - 1, column) + 1;
     //println!("furthest_distance: {furthest_distance_dl}");
     for i in 1..furthest_distance_dl {
-        if board.grid[(row + i as isize) as usize][(column - i) as usize] == color {
+        if board.grid[(row + i as isize) as usize][column - i] == color {
             //println!("DIAGONAL: {row}, {column}, {color}");
-            win_counter = win_counter + 1;
+            win_counter += 1;
         } else {
             break;
         }
-        if win_counter == 3 {
+        if win_counter == board.connect - 1 {
             if board.print {
                 println!("DIAGONAL WIN3");
             }
@@ -302,13 +1117,13 @@ pub fn game_over_check(board: &mut Board) -> bool {
     let furthest_distance_ur = min(row, (board.grid[0].len() - column - 1) as isize) + 1;
     //println!("furthest_distance: {furthest_distance_ur}");
     for i in 1..furthest_distance_ur {
-        if board.grid[(row - i) as usize][(column + i as usize) as usize] == color {
+        if board.grid[(row - i) as usize][column + i as usize] == color {
             //println!("DIAGONAL: {row}, {column}, {color}");
-            win_counter = win_counter + 1;
+            win_counter += 1;
         } else {
             break;
         }
-        if win_counter == 3 {
+        if win_counter == board.connect - 1 {
             if board.print {
                 println!("DIAGONAL WIN4");
             }
@@ -318,3 +1133,36 @@ pub fn game_over_check(board: &mut Board) -> bool {
 
     return false;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn narrow_cylinders_do_not_count_cells_twice() {
+        let mut board = new_cylinder_board(3, 4);
+        board.print = false;
+        for column in 0..3 {
+            make_move(&mut board, Move::Drop(column), 1);
+            assert!(!game_over_check(&mut board));
+            if column < 2 {
+                make_move(&mut board, Move::Drop(column), 2);
+                assert!(!game_over_check(&mut board));
+            }
+        }
+        assert!(winners(&board).is_empty());
+    }
+
+    #[test]
+    fn wide_cylinders_still_wrap() {
+        let mut board = new_cylinder_board(7, 6);
+        board.print = false;
+        for (x, o) in [(5, 5), (6, 6), (0, 0)] {
+            make_move(&mut board, Move::Drop(x), 1);
+            make_move(&mut board, Move::Drop(o), 2);
+        }
+        make_move(&mut board, Move::Drop(1), 1);
+        assert!(game_over_check(&mut board));
+        assert_eq!(winners(&board), vec![1]);
+    }
+}
@@ -0,0 +1,302 @@
+// Opening book: every position up to some ply, searched deeply ahead of
+// time and saved to disk, so bots don't have to think hard (and badly)
+// about the opening moves every game.
+//
+// File layout, all numbers little endian:
+//   "C4BK", version (u8), width (u8), height (u8), max ply (u8),
+//   search depth (u8), entry count (u32)
+//   then per entry, sorted by key: canonical position key (u64) followed
+//   by one i16 per column holding that column's score (EMPTY_VALUE for
+//   full columns), in the orientation the canonical key belongs to
+//
+// Mirror images share an entry, so only about half the positions have to
+// be searched and stored.
+use crate::board;
+use crate::board::Board;
+use crate::search;
+use crate::search::SearchResult;
+use crate::stats;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::sync::OnceLock;
+use std::thread;
+
+const MAGIC: &[u8; 4] = b"C4BK";
+const VERSION: u8 = 2;
+const HEADER_SIZE: usize = 13;
+const EMPTY_VALUE: i16 = i16::MIN;
+
+pub struct Book {
+    width: usize,
+    height: usize,
+    max_ply: usize,
+    depth: i32,
+    entries: HashMap<u64, Vec<Option<i32>>>,
+}
+
+// The book the bots and the HTTP API look at, loaded once at startup
+static GLOBAL_BOOK: OnceLock<Book> = OnceLock::new();
+
+pub fn set_global(book: Book) {
+    let _ = GLOBAL_BOOK.set(book);
+}
+
+pub fn global() -> Option<&'static Book> {
+    return GLOBAL_BOOK.get();
+}
+
+// Searches every position that can come up in the first max_ply moves,
+// skipping positions where the game is already over
+pub fn generate(width: usize, height: usize, max_ply: usize, depth: i32) -> Book {
+    let mut positions: Vec<Board> = Vec::new();
+    let mut layer = vec![board::new_board(width, height)];
+    let mut seen: HashSet<u64> = HashSet::new();
+
+    for ply in 0..=max_ply {
+        let mut next_layer = Vec::new();
+        for position in layer.iter() {
+            if ply == max_ply {
+                continue;
+            }
+            let piece = board::next_piece(position);
+            for column in board::get_empty_columns(position) {
+                let mut child = board::clone_board(position);
+                board::set_print(&mut child, false);
+                board::add_piece(&mut child, column, piece);
+                if board::game_over_check(&mut child) {
+                    continue;
+                }
+                if seen.insert(board::canonical_key(&child).0) {
+                    next_layer.push(child);
+                }
+            }
+        }
+        println!("Ply {}: {} positions", ply, layer.len());
+        positions.append(&mut layer);
+        layer = next_layer;
+    }
+
+    // Each position is searched on its own, so split them over every core
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = positions.len().div_ceil(threads);
+    let mut entries = HashMap::new();
+    thread::scope(|scope| {
+        let handles: Vec<_> = positions
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    let mut found = Vec::new();
+                    for position in chunk {
+                        // Boards that are full have no move to store
+                        if board::get_empty_columns(position).is_empty() {
+                            continue;
+                        }
+                        let result = search::search(position, depth);
+                        let (key, mirrored) = board::canonical_key(position);
+                        let mut values = result.column_values;
+                        if mirrored {
+                            values.reverse();
+                        }
+                        found.push((key, values));
+                    }
+                    return found;
+                })
+            })
+            .collect();
+        for handle in handles {
+            entries.extend(handle.join().unwrap());
+        }
+    });
+
+    return Book {
+        width,
+        height,
+        max_ply,
+        depth,
+        entries,
+    };
+}
+
+// Looks a position up and turns the stored scores back into a search result
+pub fn probe(book: &Book, board: &Board) -> Option<SearchResult> {
+    let grid = board::get_grid(board);
+    if grid.len() != book.height
+        || grid[0].len() != book.width
+        || !board::standard_scoring(board)
+        || !board::exact_keys(board)
+    {
+        return None;
+    }
+    let (key, mirrored) = board::canonical_key(board);
+    let mut values = book.entries.get(&key)?.clone();
+    if mirrored {
+        values.reverse();
+    }
+
+    let mut best_move = 0;
+    let mut best_score = i32::MIN;
+    // Same tie-breaking as the search: center columns first
+    for column in search::ordered_moves(board) {
+        if let Some(score) = values[column] {
+            if score > best_score {
+                best_score = score;
+                best_move = column;
+            }
+        }
+    }
+    if best_score == i32::MIN {
+        return None;
+    }
+    return Some(SearchResult {
+        best_move,
+        score: best_score,
+        pv: vec![best_move],
+        column_values: values,
+        // Nothing was searched just now, but the values are as deep as this
+        stats: stats::new_stats(book.depth),
+    });
+}
+
+// Probes the global book, if one was loaded
+pub fn probe_global(board: &Board) -> Option<SearchResult> {
+    return probe(global()?, board);
+}
+
+pub fn save(book: &Book, path: &str) -> io::Result<()> {
+    let mut keys: Vec<&u64> = book.entries.keys().collect();
+    keys.sort();
+
+    let mut bytes = Vec::with_capacity(HEADER_SIZE + keys.len() * (8 + 2 * book.width));
+    bytes.extend_from_slice(MAGIC);
+    bytes.push(VERSION);
+    bytes.push(book.width as u8);
+    bytes.push(book.height as u8);
+    bytes.push(book.max_ply as u8);
+    bytes.push(book.depth as u8);
+    bytes.extend_from_slice(&(keys.len() as u32).to_le_bytes());
+    for key in keys {
+        bytes.extend_from_slice(&key.to_le_bytes());
+        for value in book.entries[key].iter() {
+            bytes.extend_from_slice(&encode_score(*value).to_le_bytes());
+        }
+    }
+    return fs::write(path, bytes);
+}
+
+pub fn load(path: &str) -> io::Result<Book> {
+    let bytes = fs::read(path)?;
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    if bytes.len() < HEADER_SIZE || &bytes[0..4] != MAGIC {
+        return Err(invalid("not an opening book file"));
+    }
+    if bytes[4] != VERSION {
+        return Err(invalid("unsupported opening book version"));
+    }
+    let width = bytes[5] as usize;
+    let height = bytes[6] as usize;
+    let max_ply = bytes[7] as usize;
+    let depth = bytes[8] as i32;
+    let count = u32::from_le_bytes([bytes[9], bytes[10], bytes[11], bytes[12]]) as usize;
+
+    let entry_size = 8 + 2 * width;
+    if width == 0 || bytes.len() != HEADER_SIZE + count * entry_size {
+        return Err(invalid("opening book file is truncated"));
+    }
+    let mut entries = HashMap::with_capacity(count);
+    for entry in bytes[HEADER_SIZE..].chunks(entry_size) {
+        let key = u64::from_le_bytes(entry[0..8].try_into().unwrap());
+        let values = entry[8..]
+            .chunks(2)
+            .map(|v| decode_score(i16::from_le_bytes([v[0], v[1]])))
+            .collect();
+        entries.insert(key, values);
+    }
+
+    return Ok(Book {
+        width,
+        height,
+        max_ply,
+        depth,
+        entries,
+    });
+}
+
+pub fn describe(book: &Book) -> String {
+    return format!(
+        "{}x{} book, {} positions up to ply {}, searched to depth {}",
+        book.width,
+        book.height,
+        book.entries.len(),
+        book.max_ply,
+        book.depth
+    );
+}
+
+fn encode_score(score: Option<i32>) -> i16 {
+    match score {
+        None => return EMPTY_VALUE,
+        Some(score) => return search::pack_score(score),
+    }
+}
+
+fn decode_score(value: i16) -> Option<i32> {
+    if value == EMPTY_VALUE {
+        return None;
+    }
+    return Some(search::unpack_score(value));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_books_load_back_the_same() {
+        let book = generate(5, 4, 2, 4);
+        let path = std::env::temp_dir().join(format!("rust4-book-{}.bin", std::process::id()));
+        let path = path.to_str().unwrap();
+        save(&book, path).unwrap();
+        let loaded = load(path);
+        let _ = fs::remove_file(path);
+        let loaded = loaded.unwrap();
+
+        assert_eq!(describe(&loaded), describe(&book));
+        for key in book.entries.keys() {
+            assert_eq!(loaded.entries[key], book.entries[key]);
+        }
+        let mut board = board::new_board(5, 4);
+        for column in [1, 3] {
+            let piece = board::next_piece(&board);
+            board::add_piece(&mut board, column, piece);
+            let found = probe(&loaded, &board).unwrap();
+            let original = probe(&book, &board).unwrap();
+            assert_eq!(found.best_move, original.best_move);
+            assert_eq!(found.column_values, original.column_values);
+        }
+    }
+
+    #[test]
+    fn mirror_images_get_mirrored_scores() {
+        let book = generate(5, 4, 2, 4);
+        let mut left = board::new_board(5, 4);
+        let mut right = board::new_board(5, 4);
+        board::add_piece(&mut left, 0, 1);
+        board::add_piece(&mut right, 4, 1);
+        let mut values = probe(&book, &left).unwrap().column_values;
+        values.reverse();
+        assert_eq!(probe(&book, &right).unwrap().column_values, values);
+        assert_eq!(values, search::search(&right, 4).column_values);
+    }
+
+    #[test]
+    fn books_only_load_book_files() {
+        let path = std::env::temp_dir().join(format!("rust4-notbook-{}.bin", std::process::id()));
+        let path = path.to_str().unwrap();
+        fs::write(path, b"C4DB\x01\x05\x04").unwrap();
+        let loaded = load(path);
+        let _ = fs::remove_file(path);
+        assert_eq!(loaded.err().unwrap().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,282 @@
+// Database of solved positions that lives on disk and grows every time the
+// solver runs, so later analysis picks up where earlier runs left off.
+//
+// Positions are keyed by board::canonical_key, so a position and its mirror
+// image share a record. Moves stored for a mirrored position are flipped
+// back when they are looked up.
+//
+// File layout, all numbers little endian:
+//   "C4DB", version (u8), width (u8), height (u8)
+//   then records, appended as they are found: position key (u64),
+//   score (i16, packed with search::pack_score), best move (u8)
+use crate::board;
+use crate::board::Board;
+use crate::search;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Write;
+use std::sync::{OnceLock, RwLock};
+
+const MAGIC: &[u8; 4] = b"C4DB";
+const VERSION: u8 = 1;
+const HEADER_SIZE: usize = 7;
+const RECORD_SIZE: usize = 11;
+
+#[derive(Copy, Clone)]
+pub struct Entry {
+    // Exact score for the side to move, counted from this position
+    // (see search::to_relative)
+    pub score: i32,
+    pub best_move: usize,
+}
+
+pub struct Database {
+    path: String,
+    width: usize,
+    height: usize,
+    entries: HashMap<u64, Entry>,
+    // Records found since the last flush, still to be appended to the file
+    pending: Vec<(u64, Entry)>,
+}
+
+// Every node of every search reads it, so readers mustn't wait for each other
+static GLOBAL_DB: OnceLock<RwLock<Database>> = OnceLock::new();
+
+pub fn set_global(db: Database) {
+    let _ = GLOBAL_DB.set(RwLock::new(db));
+}
+
+pub fn probe_global(board: &Board) -> Option<Entry> {
+    return get(&GLOBAL_DB.get()?.read().unwrap(), board);
+}
+
+pub fn store_global(board: &Board, entry: Entry) {
+    if let Some(db) = GLOBAL_DB.get() {
+        insert(&mut db.write().unwrap(), board, entry);
+    }
+}
+
+pub fn flush_global() -> io::Result<()> {
+    match GLOBAL_DB.get() {
+        Some(db) => return flush(&mut db.write().unwrap()),
+        None => return Ok(()),
+    }
+}
+
+// Opens a database file, creating an empty one if it doesn't exist yet
+pub fn open(path: &str, width: usize, height: usize) -> io::Result<Database> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+    let mut db = Database {
+        path: path.to_string(),
+        width,
+        height,
+        entries: HashMap::new(),
+        pending: Vec::new(),
+    };
+
+    let bytes = match fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let mut header = MAGIC.to_vec();
+            header.extend_from_slice(&[VERSION, width as u8, height as u8]);
+            fs::write(path, header)?;
+            return Ok(db);
+        }
+        Err(e) => return Err(e),
+    };
+
+    if bytes.len() < HEADER_SIZE || &bytes[0..4] != MAGIC {
+        return Err(invalid("not a position database file"));
+    }
+    if bytes[4] != VERSION {
+        return Err(invalid("unsupported position database version"));
+    }
+    if bytes[5] as usize != width || bytes[6] as usize != height {
+        return Err(invalid("position database is for a different board size"));
+    }
+    // A run that died halfway through writing leaves a partial record at
+    // the end, which is simply ignored
+    for record in bytes[HEADER_SIZE..].chunks_exact(RECORD_SIZE) {
+        let key = u64::from_le_bytes(record[0..8].try_into().unwrap());
+        let entry = Entry {
+            score: search::unpack_score(i16::from_le_bytes([record[8], record[9]])),
+            best_move: record[10] as usize,
+        };
+        db.entries.insert(key, entry);
+    }
+    return Ok(db);
+}
+
+fn fits(db: &Database, board: &Board) -> bool {
+    // Variant positions can look just like normal ones but aren't worth
+    // the same, and hashed keys could mix two positions up for good
+    let grid = board::get_grid(board);
+    return grid.len() == db.height
+        && grid[0].len() == db.width
+        && board::standard_scoring(board)
+        && board::exact_keys(board);
+}
+
+pub fn get(db: &Database, board: &Board) -> Option<Entry> {
+    if !fits(db, board) {
+        return None;
+    }
+    let (key, mirrored) = board::canonical_key(board);
+    let mut entry = *db.entries.get(&key)?;
+    if mirrored {
+        entry.best_move = board::mirror_column(board, entry.best_move);
+    }
+    return Some(entry);
+}
+
+pub fn insert(db: &mut Database, board: &Board, mut entry: Entry) {
+    if !fits(db, board) {
+        return;
+    }
+    let (key, mirrored) = board::canonical_key(board);
+    if db.entries.contains_key(&key) {
+        return;
+    }
+    if mirrored {
+        entry.best_move = board::mirror_column(board, entry.best_move);
+    }
+    db.entries.insert(key, entry);
+    db.pending.push((key, entry));
+}
+
+// Appends everything found since the last flush to the file
+pub fn flush(db: &mut Database) -> io::Result<()> {
+    if db.pending.is_empty() {
+        return Ok(());
+    }
+    let mut bytes = Vec::with_capacity(db.pending.len() * RECORD_SIZE);
+    for (key, entry) in db.pending.iter() {
+        bytes.extend_from_slice(&key.to_le_bytes());
+        bytes.extend_from_slice(&search::pack_score(entry.score).to_le_bytes());
+        bytes.push(entry.best_move as u8);
+    }
+    let mut file = OpenOptions::new().append(true).open(&db.path)?;
+    file.write_all(&bytes)?;
+    db.pending.clear();
+    return Ok(());
+}
+
+pub fn len(db: &Database) -> usize {
+    return db.entries.len();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("rust4-{}-{}.db", name, std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        let _ = fs::remove_file(&path);
+        return path;
+    }
+
+    fn board_after(moves: &[usize]) -> Board {
+        let mut board = board::new_board(7, 6);
+        for column in moves {
+            let piece = board::next_piece(&board);
+            board::add_piece(&mut board, *column, piece);
+        }
+        return board;
+    }
+
+    #[test]
+    fn flushed_entries_load_back() {
+        let path = temp_path("roundtrip");
+        let mut db = open(&path, 7, 6).unwrap();
+        let (first, second) = (board_after(&[3]), board_after(&[3, 2, 4]));
+        insert(
+            &mut db,
+            &first,
+            Entry {
+                score: 5,
+                best_move: 3,
+            },
+        );
+        flush(&mut db).unwrap();
+        insert(
+            &mut db,
+            &second,
+            Entry {
+                score: -12,
+                best_move: 4,
+            },
+        );
+        flush(&mut db).unwrap();
+
+        let reopened = open(&path, 7, 6);
+        let wrong_size = open(&path, 6, 7);
+        let _ = fs::remove_file(&path);
+        let reopened = reopened.unwrap();
+        assert_eq!(len(&reopened), 2);
+        let entry = get(&reopened, &first).unwrap();
+        assert_eq!((entry.score, entry.best_move), (5, 3));
+        let entry = get(&reopened, &second).unwrap();
+        assert_eq!((entry.score, entry.best_move), (-12, 4));
+        assert!(get(&reopened, &board_after(&[2])).is_none());
+        assert!(wrong_size.is_err());
+    }
+
+    #[test]
+    fn mirror_images_share_a_record_with_the_move_flipped() {
+        let (left, right) = (board_after(&[1, 3]), board_after(&[5, 3]));
+        let (left_key, left_mirrored) = board::canonical_key(&left);
+        let (right_key, right_mirrored) = board::canonical_key(&right);
+        assert_eq!(left_key, right_key);
+        assert_ne!(left_mirrored, right_mirrored);
+
+        let path = temp_path("mirror");
+        let mut db = open(&path, 7, 6).unwrap();
+        insert(
+            &mut db,
+            &left,
+            Entry {
+                score: 3,
+                best_move: 2,
+            },
+        );
+        insert(
+            &mut db,
+            &right,
+            Entry {
+                score: 3,
+                best_move: 6,
+            },
+        );
+        flush(&mut db).unwrap();
+        let reopened = open(&path, 7, 6);
+        let _ = fs::remove_file(&path);
+        let reopened = reopened.unwrap();
+        assert_eq!(len(&reopened), 1);
+        assert_eq!(get(&reopened, &left).unwrap().best_move, 2);
+        assert_eq!(get(&reopened, &right).unwrap().best_move, 4);
+    }
+
+    #[test]
+    fn half_written_records_are_ignored() {
+        let path = temp_path("partial");
+        let mut db = open(&path, 7, 6).unwrap();
+        insert(
+            &mut db,
+            &board_after(&[0]),
+            Entry {
+                score: 1,
+                best_move: 1,
+            },
+        );
+        flush(&mut db).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[1, 2, 3]).unwrap();
+
+        let reopened = open(&path, 7, 6);
+        let _ = fs::remove_file(&path);
+        assert_eq!(len(&reopened.unwrap()), 1);
+    }
+}
@@ -0,0 +1,168 @@
+// Named difficulty levels for playing against the computer, beginner to
+// perfect. Each is the alpha-beta search from search.rs held back in some
+// way: how deep it may look, how long it may think, how much noise gets
+// added to its column scores and how often it plays a bad move on purpose.
+//
+// Calibrated with the tournament command, each level playing 20 games with
+// each color against the one below it, every game starting with 4 random
+// moves (tournament ... 20 --opening 4). Wins-losses-ties for the stronger
+// level:
+//
+//   easy    vs beginner  32-8-0
+//   medium  vs easy      28-10-2
+//   hard    vs medium    32-5-3
+//   expert  vs hard      22-13-5
+//   perfect vs expert    30-7-3
+//
+// Without the opening book perfect is only perfect from SOLVE_EMPTY empty
+// cells on, before that it's the deepest search that fits in its time.
+use crate::board;
+use crate::board::Board;
+use crate::book;
+use crate::database;
+use crate::search;
+use rand::{seq::SliceRandom, thread_rng, Rng};
+use std::time::Instant;
+
+pub struct Level {
+    pub name: &'static str,
+    // Deepest search, in plies
+    pub depth: i32,
+    // Thinking time per move in milliseconds, 0 for no limit. Deeper
+    // searches only start if they look like they'll finish in time. Only
+    // perfect needs one: up to depth 8 the searches take a few dozen
+    // milliseconds at most, perfect would go on to depth 42.
+    pub time_ms: u128,
+    // Column scores get up to this much added or taken away at random.
+    // Forced wins and losses are left alone.
+    pub noise: i32,
+    // Chance of playing a random move that isn't the best one
+    pub blunder: f64,
+    // Whether to use the opening book, the solved positions and solve the
+    // rest of the game outright once it's small enough
+    pub perfect: bool,
+}
+
+pub const LEVELS: [Level; 6] = [
+    Level {
+        name: "beginner",
+        depth: 1,
+        time_ms: 0,
+        noise: 60,
+        blunder: 0.3,
+        perfect: false,
+    },
+    Level {
+        name: "easy",
+        depth: 2,
+        time_ms: 0,
+        noise: 30,
+        blunder: 0.15,
+        perfect: false,
+    },
+    Level {
+        name: "medium",
+        depth: 4,
+        time_ms: 0,
+        noise: 15,
+        blunder: 0.05,
+        perfect: false,
+    },
+    Level {
+        name: "hard",
+        depth: 6,
+        time_ms: 0,
+        noise: 5,
+        blunder: 0.0,
+        perfect: false,
+    },
+    Level {
+        name: "expert",
+        depth: 8,
+        time_ms: 0,
+        noise: 0,
+        blunder: 0.0,
+        perfect: false,
+    },
+    Level {
+        name: "perfect",
+        depth: 42,
+        time_ms: 5000,
+        noise: 0,
+        blunder: 0.0,
+        perfect: true,
+    },
+];
+
+// Player type of the first level, the rest follow in order (see
+// player::parse_player_type)
+pub const FIRST_LEVEL_TYPE: i8 = 8;
+
+// A standard board with this many empty cells or fewer solves in under half
+// a second, usually in a few dozen milliseconds (100 random positions in a
+// release build). With 24 empty cells the median is still under 0.1 s but
+// some positions take minutes.
+pub const SOLVE_EMPTY: usize = 20;
+
+pub fn level(player_type: i8) -> Option<&'static Level> {
+    if player_type < FIRST_LEVEL_TYPE {
+        return None;
+    }
+    return LEVELS.get((player_type - FIRST_LEVEL_TYPE) as usize);
+}
+
+pub fn level_move(level: &Level, board: &Board) -> usize {
+    if level.perfect {
+        if let Some(result) = book::probe_global(board) {
+            return result.best_move;
+        }
+        if let Some(entry) = database::probe_global(board) {
+            return entry.best_move;
+        }
+        if board::empty_cells(board) <= SOLVE_EMPTY {
+            return search::solve(board).best_move;
+        }
+    }
+
+    // Iterative deepening, so a time limit still leaves a whole search to
+    // go on. Each depth takes a few times as long as the one before.
+    let start = Instant::now();
+    let mut result = search::search(board, 1);
+    for depth in 2..=level.depth {
+        if level.time_ms > 0 && start.elapsed().as_millis() * 4 > level.time_ms {
+            break;
+        }
+        result = search::search(board, depth);
+        if search::is_win_score(result.score) {
+            break;
+        }
+    }
+
+    let mut rng = thread_rng();
+    let columns = board::get_empty_columns(board);
+    if columns.len() > 1 && rng.gen_bool(level.blunder) {
+        let others: Vec<&usize> = columns.iter().filter(|c| **c != result.best_move).collect();
+        return **others.choose(&mut rng).unwrap();
+    }
+
+    // Best score after the noise, picking at random between equal ones so
+    // the same position doesn't always get the same answer
+    let mut scored = Vec::new();
+    for column in columns {
+        if let Some(score) = result.column_values[column] {
+            let noise = if search::is_win_score(score) || level.noise == 0 {
+                0
+            } else {
+                rng.gen_range(-level.noise..=level.noise)
+            };
+            scored.push((column, score + noise));
+        }
+    }
+    let best = scored.iter().map(|(_, score)| *score).max().unwrap();
+    let best_columns: Vec<usize> = scored
+        .iter()
+        .filter(|(_, score)| *score == best)
+        .map(|(column, _)| *column)
+        .collect();
+    return *best_columns.choose(&mut rng).unwrap();
+}
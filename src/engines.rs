@@ -0,0 +1,250 @@
+// Engines that only know about the Rules trait, so they play every variant,
+// including ones that aren't played on a Board. They are weaker than the
+// Board specific bots, which know the game and cut corners accordingly.
+use crate::board;
+use crate::board::Move;
+use crate::rules::Rules;
+use crate::search::WIN_SCORE;
+use crate::stats;
+use crate::stats::SearchStats;
+use rand::{seq::SliceRandom, thread_rng, Rng};
+use std::time::Instant;
+
+// How far a single random playout can go before it's called a draw
+const MAX_PLAYOUT: usize = 1000;
+// Exploration constant for UCB1, sqrt(2) is the textbook value
+const EXPLORATION: f64 = 1.41;
+
+// A copy to search on, with the board's WIN debug lines switched off
+fn search_copy(rules: &dyn Rules) -> Box<dyn Rules> {
+    let mut copy = rules.clone_rules();
+    if let Some(board) = copy.board_mut() {
+        board::set_print(board, false);
+    }
+    return copy;
+}
+
+pub fn random_move(rules: &dyn Rules) -> Move {
+    let mut rng = thread_rng();
+    return *rules.legal_moves().choose(&mut rng).unwrap();
+}
+
+// Alpha-beta for the player to move against everyone else. With two
+// players that's plain alpha-beta, with more it's the paranoid search from
+// multisearch.rs.
+pub fn alphabeta_move(rules: &dyn Rules, depth: i32) -> Move {
+    return alphabeta_search(rules, depth).0;
+}
+
+// alphabeta_move, along with the search's counters
+pub fn alphabeta_search(rules: &dyn Rules, depth: i32) -> (Move, SearchStats) {
+    let start = Instant::now();
+    let mut stats = stats::new_stats(depth);
+    let mut copy = search_copy(rules);
+    let root = copy.to_move();
+    let moves = copy.legal_moves();
+
+    let mut best_move = moves[0];
+    let mut best_score = i32::MIN;
+    for player_move in moves {
+        copy.apply(player_move);
+        let score = alphabeta(
+            copy.as_mut(),
+            depth - 1,
+            best_score,
+            i32::MAX,
+            root,
+            1,
+            &mut stats,
+        );
+        copy.undo();
+        if score > best_score {
+            best_score = score;
+            best_move = player_move;
+        }
+    }
+    stats::finish(&mut stats, start);
+    return (best_move, stats);
+}
+
+// Score for root, which is trying to maximize it while every other player
+// is trying to minimize it. ply moves have been played since the root.
+#[allow(clippy::too_many_arguments)]
+fn alphabeta(
+    rules: &mut dyn Rules,
+    depth: i32,
+    mut alpha: i32,
+    mut beta: i32,
+    root: usize,
+    ply: i32,
+    stats: &mut SearchStats,
+) -> i32 {
+    stats::node(stats, ply);
+    let result = rules.result();
+    if result == root {
+        return WIN_SCORE - ply;
+    }
+    if result == rules.players() + 1 {
+        return 0;
+    }
+    if result != 0 {
+        return -(WIN_SCORE - ply);
+    }
+    if depth <= 0 {
+        return rules.evaluate(root);
+    }
+
+    let maximizing = rules.to_move() == root;
+    let mut best_score = if maximizing { i32::MIN } else { i32::MAX };
+    let mut searched = 0;
+    for (index, player_move) in rules.legal_moves().into_iter().enumerate() {
+        searched += 1;
+        rules.apply(player_move);
+        let score = alphabeta(rules, depth - 1, alpha, beta, root, ply + 1, stats);
+        rules.undo();
+
+        if maximizing {
+            best_score = best_score.max(score);
+            alpha = alpha.max(score);
+        } else {
+            best_score = best_score.min(score);
+            beta = beta.min(score);
+        }
+        if alpha >= beta {
+            stats::cutoff(stats, index);
+            break;
+        }
+    }
+    stats::expanded(stats, searched);
+    return best_score;
+}
+
+// Monte Carlo tree search node
+struct Node {
+    // Move that led here from the parent, None at the root
+    player_move: Option<Move>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+    // Moves not tried from here yet
+    untried: Vec<Move>,
+    // Piece that made player_move, the rewards are from its point of view
+    mover: usize,
+    visits: f64,
+    reward: f64,
+}
+
+// Monte Carlo tree search (UCT): grows a tree towards the moves that win
+// the most random playouts, then plays the root's most visited move
+pub fn mcts_move(rules: &dyn Rules, iterations: usize) -> Move {
+    return mcts_search(rules, iterations).0;
+}
+
+// mcts_move, along with the search's counters. Every move played in the
+// tree or in a playout counts as a node and depth is the iterations.
+pub fn mcts_search(rules: &dyn Rules, iterations: usize) -> (Move, SearchStats) {
+    let start = Instant::now();
+    let mut stats = stats::new_stats(iterations as i32);
+    let mut copy = search_copy(rules);
+    let mut rng = thread_rng();
+    let mut nodes = vec![Node {
+        player_move: None,
+        parent: None,
+        children: Vec::new(),
+        untried: copy.legal_moves(),
+        mover: 0,
+        visits: 0.0,
+        reward: 0.0,
+    }];
+
+    for _ in 0..iterations {
+        // Selection: follow the best UCB1 child while everything is tried
+        let mut current = 0;
+        let mut depth = 0;
+        while nodes[current].untried.is_empty() && !nodes[current].children.is_empty() {
+            let parent_visits = nodes[current].visits;
+            current = *nodes[current]
+                .children
+                .iter()
+                .max_by(|a, b| {
+                    ucb(&nodes[**a], parent_visits).total_cmp(&ucb(&nodes[**b], parent_visits))
+                })
+                .unwrap();
+            copy.apply(nodes[current].player_move.unwrap());
+            depth += 1;
+            stats::node(&mut stats, depth as i32);
+        }
+
+        // Expansion: add one untried move, unless the game is over here
+        if !nodes[current].untried.is_empty() && copy.result() == 0 {
+            let i = rng.gen_range(0..nodes[current].untried.len());
+            let player_move = nodes[current].untried.swap_remove(i);
+            let mover = copy.to_move();
+            copy.apply(player_move);
+            depth += 1;
+            stats::node(&mut stats, depth as i32);
+            let untried = if copy.result() == 0 {
+                copy.legal_moves()
+            } else {
+                Vec::new()
+            };
+            nodes.push(Node {
+                player_move: Some(player_move),
+                parent: Some(current),
+                children: Vec::new(),
+                untried,
+                mover,
+                visits: 0.0,
+                reward: 0.0,
+            });
+            let child = nodes.len() - 1;
+            nodes[current].children.push(child);
+            current = child;
+        }
+
+        // Simulation: random moves until the game ends
+        let mut result = copy.result();
+        let mut playout = 0;
+        while result == 0 && playout < MAX_PLAYOUT {
+            let player_move = *copy.legal_moves().choose(&mut rng).unwrap();
+            copy.apply(player_move);
+            playout += 1;
+            stats::node(&mut stats, (depth + playout) as i32);
+            result = copy.result();
+        }
+        for _ in 0..playout + depth {
+            copy.undo();
+        }
+
+        // Backpropagation: a win is worth 1 to the winner, a draw (or a
+        // playout that went on too long) half that to everyone
+        let tie = result == 0 || result == copy.players() + 1;
+        let mut node = Some(current);
+        while let Some(i) = node {
+            nodes[i].visits += 1.0;
+            if tie {
+                nodes[i].reward += 0.5;
+            } else if nodes[i].mover == result {
+                nodes[i].reward += 1.0;
+            }
+            node = nodes[i].parent;
+        }
+    }
+
+    let best = nodes[0]
+        .children
+        .iter()
+        .max_by(|a, b| nodes[**a].visits.total_cmp(&nodes[**b].visits));
+    let best_move = match best {
+        Some(child) => nodes[*child].player_move.unwrap(),
+        None => random_move(rules),
+    };
+    stats::finish(&mut stats, start);
+    return (best_move, stats);
+}
+
+fn ucb(node: &Node, parent_visits: f64) -> f64 {
+    if node.visits == 0.0 {
+        return f64::INFINITY;
+    }
+    return node.reward / node.visits + EXPLORATION * (parent_visits.ln() / node.visits).sqrt();
+}
@@ -0,0 +1,197 @@
+// Why a bot played what it played, in a few words. The game loop shows this
+// next to every bot move when it's printing, so people watching can tell a
+// block from a random drop.
+//
+// The immediate stuff (wins, blocks, threats made) comes straight from the
+// board and threats.rs. How the game goes from here comes from a search of
+// the position before the move, which needs two players and gravity, so
+// other boards only get the immediate reasons.
+use crate::board;
+use crate::board::{Board, Move};
+use crate::rules::Rules;
+use crate::search;
+use crate::search::WIN_SCORE;
+use crate::threats;
+
+// How deep the search behind the explanations looks
+const EXPLAIN_DEPTH: i32 = 8;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Reason {
+    // Completes a line (under misère rules that loses, see LosesNow)
+    WinsNow,
+    LosesNow,
+    // Takes a cell the opponent would have won on next move, 1-based column
+    BlocksThreat(usize),
+    // Leaves two or more threats the opponent can't both stop
+    CreatesDoubleThreat,
+    // Makes a new threat, 1-based column and whether its row is odd
+    CreatesThreat(usize, bool),
+    // The search found a win in this many of the mover's own moves
+    ForcedWin(i32),
+    // Every other move loses
+    OnlyNonLosing,
+    // The opponent can force a win in this many of their moves anyway
+    LosesIn(i32),
+    // Loses in this many of the opponent's moves, when another move didn't
+    WalksIntoLoss(i32),
+    // Doesn't lose, but there was a forced win in this many moves
+    MissesWin(i32),
+}
+
+pub fn describe(reason: &Reason) -> String {
+    match reason {
+        Reason::WinsNow => return "wins the game".to_string(),
+        Reason::LosesNow => return "completes a line, which loses".to_string(),
+        Reason::BlocksThreat(column) => {
+            return format!("blocks immediate threat in column {}", column)
+        }
+        Reason::CreatesDoubleThreat => return "creates double threat".to_string(),
+        Reason::CreatesThreat(column, odd) => {
+            let parity = if *odd { "odd" } else { "even" };
+            return format!("creates {} threat in column {}", parity, column);
+        }
+        Reason::ForcedWin(moves) => return format!("forced win in {}", moves),
+        Reason::OnlyNonLosing => return "only non-losing move".to_string(),
+        Reason::LosesIn(moves) => return format!("loses in {} whatever is played", moves),
+        Reason::WalksIntoLoss(moves) => return format!("walks into a loss in {}", moves),
+        Reason::MissesWin(moves) => return format!("misses a forced win in {}", moves),
+    }
+}
+
+// The reasons joined up, or None when there's nothing to say
+pub fn explain_text(rules: &dyn Rules, player_move: Move) -> Option<String> {
+    let reasons = explain(rules, player_move);
+    if reasons.is_empty() {
+        return None;
+    }
+    let text: Vec<String> = reasons.iter().map(describe).collect();
+    return Some(text.join(", "));
+}
+
+// Reasons for playing player_move in the position before it's played.
+// Rule sets without a board don't get any.
+pub fn explain(rules: &dyn Rules, player_move: Move) -> Vec<Reason> {
+    let board = match rules.board() {
+        Some(board) => board,
+        None => return Vec::new(),
+    };
+    let cell = match landing_cell(board, player_move) {
+        Some(cell) => cell,
+        None => return Vec::new(),
+    };
+    let piece = board::next_piece(board);
+    let mut reasons = Vec::new();
+
+    let mut after = board::clone_board(board);
+    board::set_print(&mut after, false);
+    board::make_move(&mut after, player_move, piece);
+    if board::game_over_check(&mut after) {
+        if board::is_misere(board) {
+            reasons.push(Reason::LosesNow);
+        } else {
+            reasons.push(Reason::WinsNow);
+        }
+        return reasons;
+    }
+
+    // Immediate threats of the others that this move sits on
+    let before = threats::analyze(board);
+    let blocked = before
+        .threats
+        .iter()
+        .any(|t| t.piece != piece && t.playable && (t.row, t.column) == cell);
+    if blocked && !board::is_misere(board) {
+        reasons.push(Reason::BlocksThreat(cell.1 + 1));
+    }
+
+    // New threats for the mover
+    let now = threats::analyze(&after);
+    let own: Vec<&threats::Threat> = now.threats.iter().filter(|t| t.piece == piece).collect();
+    if !board::is_misere(board) {
+        if double_threat(&own) {
+            reasons.push(Reason::CreatesDoubleThreat);
+        } else {
+            let new = own.iter().find(|t| {
+                !before
+                    .threats
+                    .iter()
+                    .any(|old| old.piece == piece && (old.row, old.column) == (t.row, t.column))
+            });
+            if let Some(threat) = new {
+                reasons.push(Reason::CreatesThreat(threat.column + 1, threat.odd));
+            }
+        }
+    }
+
+    if let Move::Drop(column) = player_move {
+        // The search only drops pieces, so in PopOut its values miss the pops
+        if board::players(board) == 2 && board::has_gravity(board) && !board::is_popout(board) {
+            reasons.extend(search_reasons(board, column));
+        }
+    }
+    return reasons;
+}
+
+// Where the move puts a piece, (row, column). Pops don't put one anywhere.
+fn landing_cell(board: &Board, player_move: Move) -> Option<(usize, usize)> {
+    match player_move {
+        Move::Drop(column) => {
+            return board.lowest_empty[column]
+                .checked_sub(1)
+                .map(|row| (row, column))
+        }
+        Move::Place(row, column) => return Some((row, column)),
+        Move::Pop(_) => return None,
+    }
+}
+
+// Two wins in one, or one threat right on top of another so blocking the
+// lower one hands over the upper one
+fn double_threat(own: &[&threats::Threat]) -> bool {
+    if own.iter().filter(|t| t.playable).count() >= 2 {
+        return true;
+    }
+    return own.iter().any(|lower| {
+        lower.playable
+            && own
+                .iter()
+                .any(|upper| upper.column == lower.column && upper.row + 1 == lower.row)
+    });
+}
+
+fn search_reasons(board: &Board, column: usize) -> Vec<Reason> {
+    let result = search::search(board, EXPLAIN_DEPTH);
+    let score = match result.column_values[column] {
+        Some(score) => score,
+        None => return Vec::new(),
+    };
+    let others: Vec<i32> = result
+        .column_values
+        .iter()
+        .enumerate()
+        .filter(|(other, _)| *other != column)
+        .filter_map(|(_, value)| *value)
+        .collect();
+
+    let mut reasons = Vec::new();
+    let losing = |score: i32| search::is_win_score(score) && score < 0;
+    if search::is_win_score(score) && score > 0 {
+        reasons.push(Reason::ForcedWin(own_moves(score)));
+    } else if losing(score) && score == result.score {
+        reasons.push(Reason::LosesIn(own_moves(-score)));
+    } else if losing(score) {
+        reasons.push(Reason::WalksIntoLoss(own_moves(-score)));
+    } else if search::is_win_score(result.score) && result.score > 0 {
+        reasons.push(Reason::MissesWin(own_moves(result.score)));
+    } else if !others.is_empty() && others.iter().all(|value| losing(*value)) {
+        reasons.push(Reason::OnlyNonLosing);
+    }
+    return reasons;
+}
+
+// Moves the winner needs for a win score. Scores are WIN_SCORE minus the
+// plies to the end, and whoever wins plays the last of them.
+fn own_moves(score: i32) -> i32 {
+    return (WIN_SCORE - score + 1) / 2;
+}
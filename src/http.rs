@@ -0,0 +1,409 @@
+// Small local HTTP service that exposes the search as JSON, so tools that
+// aren't written in Rust can ask for engine analysis.
+//
+//   POST /bestmove  {"moves": "4453", "engine": "alphabeta", "depth": 8,
+//                    "time_ms": 1000}
+//
+// "moves" is the position as a string of 1-based columns, everything else
+// is optional. "engine" is one of
+//
+//   alphabeta  search.rs to "depth" plies (the default). With "time_ms" it
+//              deepens one ply at a time and stops before a depth that
+//              looks like it won't finish in time.
+//   solve      search.rs to the end of the game. Only for positions with
+//              at most difficulty::SOLVE_EMPTY empty cells, which solve in
+//              under half a second (see difficulty.rs). Earlier positions
+//              can take hours.
+//   mcts       Monte Carlo tree search with "iterations" playouts. It has
+//              no scores, so "score" and "values" are null.
+//
+// The reply has the best column, its score, the principal variation and the
+// score of every column (null for full ones), all with 1-based columns.
+// "source" says where the answer came from: "book" for positions alphabeta
+// found in the opening book, otherwise the engine's name. "depth" is how
+// deep the answer looked, the book's depth for book answers. "stats" has
+// the search's counters, see stats.rs.
+//
+//   POST /threats   {"moves": "4453"}
+//
+// Threat analysis of the position (see threats.rs): every threat with its
+// player, column, row counted from the bottom and parity, who controls
+// zugzwang and the claimeven/baseinverse/vertical rules the player who
+// isn't to move can answer with. Players are 1 and 2.
+use crate::board;
+use crate::board::{Board, Move};
+use crate::book;
+use crate::database;
+use crate::difficulty;
+use crate::engines;
+use crate::rules;
+use crate::search;
+use crate::search::SearchResult;
+use crate::stats;
+use crate::stats::SearchStats;
+use crate::threats;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Instant;
+
+const WIDTH: usize = 7;
+const HEIGHT: usize = 6;
+const DEFAULT_DEPTH: i32 = 8;
+const MAX_DEPTH: i32 = 14;
+const DEFAULT_ITERATIONS: usize = 5000;
+const MAX_ITERATIONS: usize = 1_000_000;
+// Nobody needs to send more than a move string and a few parameters
+const MAX_BODY: usize = 64 * 1024;
+
+#[derive(Deserialize)]
+struct BestMoveRequest {
+    moves: String,
+    engine: Option<String>,
+    depth: Option<i32>,
+    time_ms: Option<u64>,
+    iterations: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct BestMoveResponse {
+    bestmove: usize,
+    score: Option<i32>,
+    pv: Vec<usize>,
+    values: Vec<Option<i32>>,
+    // Plies looked ahead, null for mcts
+    depth: Option<i32>,
+    nodes: u64,
+    source: &'static str,
+    book: bool,
+    stats: StatsJson,
+}
+
+// The search's counters plus the rates worked out from them, see stats.rs
+#[derive(Serialize)]
+struct StatsJson {
+    nps: f64,
+    tt_hit_rate: f64,
+    first_move_cutoff_rate: f64,
+    branching_factor: f64,
+    #[serde(flatten)]
+    counts: SearchStats,
+}
+
+#[derive(Deserialize)]
+struct ThreatsRequest {
+    moves: String,
+}
+
+#[derive(Serialize)]
+struct ThreatJson {
+    player: usize,
+    column: usize,
+    row: usize,
+    odd: bool,
+    playable: bool,
+}
+
+#[derive(Serialize)]
+struct RuleJson {
+    rule: &'static str,
+    // [column, row] pairs, lower cell first
+    cells: Vec<[usize; 2]>,
+    refutes: usize,
+}
+
+#[derive(Serialize)]
+struct ThreatsResponse {
+    threats: Vec<ThreatJson>,
+    zugzwang: Option<usize>,
+    defender: usize,
+    rules: Vec<RuleJson>,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+pub fn run(addr: &str) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    println!("HTTP API listening on {}", addr);
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                thread::spawn(move || handle_connection(stream));
+            }
+            Err(e) => println!("Failed to accept connection: {}", e),
+        }
+    }
+    return Ok(());
+}
+
+fn handle_connection(mut stream: TcpStream) {
+    let (status, body) = match read_request(&stream) {
+        Ok((method, path, body)) => route(&method, &path, &body),
+        Err(message) => (400, error_json(&message)),
+    };
+    let reason = match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        _ => "Internal Server Error",
+    };
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason,
+        body.len(),
+        body
+    );
+    let _ = stream.write_all(response.as_bytes());
+}
+
+// Reads the request line, the headers we care about and the body
+pub fn read_request(stream: impl Read) -> Result<(String, String, String), String> {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    if reader.read_line(&mut line).is_err() {
+        return Err("could not read request".to_string());
+    }
+    let parts: Vec<&str> = line.split_whitespace().collect();
+    if parts.len() < 2 {
+        return Err("malformed request line".to_string());
+    }
+    let method = parts[0].to_string();
+    let path = parts[1].to_string();
+
+    let mut content_length = 0;
+    loop {
+        line.clear();
+        match reader.read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(_) => return Err("could not read headers".to_string()),
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                content_length = match value.trim().parse::<usize>() {
+                    Ok(length) => length,
+                    Err(_) => return Err("invalid Content-Length".to_string()),
+                };
+            }
+        }
+    }
+    if content_length > MAX_BODY {
+        return Err("request body too large".to_string());
+    }
+
+    let mut body = vec![0; content_length];
+    if reader.read_exact(&mut body).is_err() {
+        return Err("could not read body".to_string());
+    }
+    match String::from_utf8(body) {
+        Ok(body) => return Ok((method, path, body)),
+        Err(_) => return Err("body is not valid UTF-8".to_string()),
+    }
+}
+
+// Returns the HTTP status code and the JSON body to send back
+pub fn route(method: &str, path: &str, body: &str) -> (u16, String) {
+    match path {
+        "/bestmove" => {
+            if method != "POST" {
+                return (405, error_json("use POST"));
+            }
+            return best_move(body);
+        }
+        "/threats" => {
+            if method != "POST" {
+                return (405, error_json("use POST"));
+            }
+            return threats(body);
+        }
+        _ => return (404, error_json("not found")),
+    }
+}
+
+fn best_move(body: &str) -> (u16, String) {
+    let request: BestMoveRequest = match serde_json::from_str(body) {
+        Ok(request) => request,
+        Err(e) => return (400, error_json(&format!("invalid JSON: {}", e))),
+    };
+    let engine = request.engine.as_deref().unwrap_or("alphabeta");
+    if !["alphabeta", "solve", "mcts"].contains(&engine) {
+        return (400, error_json("engine must be alphabeta, solve or mcts"));
+    }
+    // With a time limit the depth is only a cap, so it can be as deep as
+    // allowed
+    let default_depth = if request.time_ms.is_some() {
+        MAX_DEPTH
+    } else {
+        DEFAULT_DEPTH
+    };
+    let depth = request.depth.unwrap_or(default_depth);
+    if !(1..=MAX_DEPTH).contains(&depth) {
+        return (
+            400,
+            error_json(&format!("depth must be between 1 and {}", MAX_DEPTH)),
+        );
+    }
+    if request.time_ms.is_some() && engine != "alphabeta" {
+        return (400, error_json("time_ms only works with alphabeta"));
+    }
+    let iterations = request.iterations.unwrap_or(DEFAULT_ITERATIONS);
+    if !(1..=MAX_ITERATIONS).contains(&iterations) {
+        return (
+            400,
+            error_json(&format!(
+                "iterations must be between 1 and {}",
+                MAX_ITERATIONS
+            )),
+        );
+    }
+    let board = match board::from_move_string(&request.moves, WIDTH, HEIGHT) {
+        Ok(board) => board,
+        Err(e) => return (400, error_json(&e)),
+    };
+    if board::get_empty_columns(&board).is_empty() {
+        return (400, error_json("board is full"));
+    }
+    if engine == "solve" && board::empty_cells(&board) > difficulty::SOLVE_EMPTY {
+        return (
+            400,
+            error_json(&format!(
+                "solve needs at most {} empty cells",
+                difficulty::SOLVE_EMPTY
+            )),
+        );
+    }
+
+    let (result, source) = match engine {
+        "mcts" => (mcts(&board, iterations), "mcts"),
+        "solve" => (search::solve(&board), "solve"),
+        _ => match book::probe_global(&board) {
+            Some(result) => (result, "book"),
+            None => match request.time_ms {
+                Some(time_ms) => (timed_search(&board, depth, time_ms), "alphabeta"),
+                None => (search::search(&board, depth), "alphabeta"),
+            },
+        },
+    };
+    // Searches that reach the end of the game add to the solved positions
+    if let Err(e) = database::flush_global() {
+        println!("Could not save to database: {}", e);
+    }
+    let scored = source != "mcts";
+    let response = BestMoveResponse {
+        bestmove: result.best_move + 1,
+        score: if scored { Some(result.score) } else { None },
+        pv: result.pv.iter().map(|c| c + 1).collect(),
+        values: result.column_values,
+        depth: if scored {
+            Some(result.stats.depth)
+        } else {
+            None
+        },
+        nodes: result.stats.nodes,
+        source,
+        book: source == "book",
+        stats: StatsJson {
+            nps: stats::nodes_per_second(&result.stats),
+            tt_hit_rate: stats::tt_hit_rate(&result.stats),
+            first_move_cutoff_rate: stats::first_move_cutoff_rate(&result.stats),
+            branching_factor: stats::branching_factor(&result.stats),
+            counts: result.stats.clone(),
+        },
+    };
+    return (200, serde_json::to_string(&response).unwrap());
+}
+
+// Iterative deepening up to depth, like the difficulty levels: each depth
+// takes a few times as long as the one before, so the next one only starts
+// while there's plenty of time left. The result is from the deepest search,
+// the stats add up all of them.
+fn timed_search(board: &Board, depth: i32, time_ms: u64) -> SearchResult {
+    let start = Instant::now();
+    let mut result = search::search(board, 1);
+    let mut total = result.stats.clone();
+    for depth in 2..=depth {
+        if start.elapsed().as_millis() * 4 > time_ms as u128 || search::is_win_score(result.score) {
+            break;
+        }
+        result = search::search(board, depth);
+        stats::add(&mut total, &result.stats);
+    }
+    result.stats = total;
+    return result;
+}
+
+// MCTS only picks a move, so the rest of the result is left empty
+fn mcts(board: &Board, iterations: usize) -> SearchResult {
+    let (player_move, stats) =
+        engines::mcts_search(&rules::connect(board::clone_board(board)), iterations);
+    let best_move = match player_move {
+        Move::Drop(column) | Move::Pop(column) | Move::Place(_, column) => column,
+    };
+    return SearchResult {
+        best_move,
+        score: 0,
+        pv: vec![best_move],
+        column_values: vec![None; WIDTH],
+        stats,
+    };
+}
+
+fn threats(body: &str) -> (u16, String) {
+    let request: ThreatsRequest = match serde_json::from_str(body) {
+        Ok(request) => request,
+        Err(e) => return (400, error_json(&format!("invalid JSON: {}", e))),
+    };
+    let board = match board::from_move_string(&request.moves, WIDTH, HEIGHT) {
+        Ok(board) => board,
+        Err(e) => return (400, error_json(&e)),
+    };
+
+    let analysis = threats::analyze(&board);
+    let response = ThreatsResponse {
+        threats: analysis
+            .threats
+            .iter()
+            .map(|t| ThreatJson {
+                player: t.piece,
+                column: t.column + 1,
+                row: threats::row_number(&board, t.row),
+                odd: t.odd,
+                playable: t.playable,
+            })
+            .collect(),
+        zugzwang: analysis.zugzwang,
+        defender: analysis.defender,
+        rules: analysis
+            .applications
+            .iter()
+            .map(|a| RuleJson {
+                rule: threats::rule_name(a.rule),
+                cells: a
+                    .cells
+                    .iter()
+                    .map(|(row, col)| [col + 1, threats::row_number(&board, *row)])
+                    .collect(),
+                refutes: a.refutes,
+            })
+            .collect(),
+    };
+    return (200, serde_json::to_string(&response).unwrap());
+}
+
+fn error_json(message: &str) -> String {
+    let response = ErrorResponse {
+        error: message.to_string(),
+    };
+    return serde_json::to_string(&response).unwrap();
+}
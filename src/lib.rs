@@ -0,0 +1,22 @@
+// The game, its variants and the engines, as a library so the benchmarks,
+// tests and fuzz targets can get at them. main.rs is the command line.
+#![allow(clippy::needless_return)]
+pub mod batch;
+pub mod board;
+pub mod book;
+pub mod database;
+pub mod difficulty;
+pub mod engines;
+pub mod explain;
+pub mod http;
+pub mod multisearch;
+pub mod player;
+pub mod popten;
+pub mod puzzle;
+pub mod record;
+pub mod rules;
+pub mod search;
+pub mod server;
+pub mod stats;
+pub mod threats;
+pub mod trainer;
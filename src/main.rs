@@ -1,9 +1,13 @@
 // use rand::{random, seq::SliceRandom, thread_rng, Rng};
 // use std::{cmp::min, io, os::windows::thread};
+#![allow(clippy::needless_return)]
+use rules::Rules;
+use rust4::{
+    batch, board, book, database, engines, explain, http, multisearch, player, popten, puzzle,
+    record, rules, search, server, stats, trainer,
+};
 use std::env;
-mod board;
-mod player;
-use rand::thread_rng;
+use std::fs;
 use std::{thread, time};
 
 const WIDTH: usize = 7;
@@ -12,6 +16,132 @@ const TOTAL_GAMES: f32 = 100000.0;
 
 fn main() {
     env::set_var("RUST_BACKTRACE", "1");
+
+    let mut args: Vec<String> = env::args().collect();
+
+    // --book <file> can go in front of any command and loads an opening
+    // book for the bots to use
+    let book_path = match take_option(&mut args, "--book") {
+        Ok(path) => path,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    if let Some(path) = book_path {
+        match book::load(&path) {
+            Ok(loaded) => {
+                println!("Loaded {}", book::describe(&loaded));
+                book::set_global(loaded);
+            }
+            Err(e) => {
+                println!("Could not load book {}: {}", path, e);
+                return;
+            }
+        }
+    }
+
+    // --db <file> opens (or creates) the solved position database, which
+    // the searches read from and the solver adds to
+    let db_path = match take_option(&mut args, "--db") {
+        Ok(path) => path,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    if let Some(path) = db_path {
+        match database::open(&path, WIDTH, HEIGHT) {
+            Ok(db) => {
+                println!(
+                    "Opened {} with {} solved positions",
+                    path,
+                    database::len(&db)
+                );
+                database::set_global(db);
+            }
+            Err(e) => {
+                println!("Could not open database {}: {}", path, e);
+                return;
+            }
+        }
+    }
+
+    if args.len() > 1 {
+        if ["serve", "http"].contains(&args[1].as_str()) {
+            save_database_every(DB_SAVE_INTERVAL);
+        }
+        match args[1].as_str() {
+            "serve" => {
+                let addr = args.get(2).map(|a| a.as_str()).unwrap_or("0.0.0.0:4000");
+                if let Err(e) = server::run(addr) {
+                    println!("Server error: {}", e);
+                }
+            }
+            "http" => {
+                let addr = args.get(2).map(|a| a.as_str()).unwrap_or("127.0.0.1:8080");
+                if let Err(e) = http::run(addr) {
+                    println!("HTTP API error: {}", e);
+                }
+            }
+            "play" => play_command(&args[2..]),
+            "tournament" => tournament_command(&args[2..]),
+            "replay" => replay_command(&args[2..]),
+            "book" => book_command(&args[2..]),
+            "solve" => solve_command(args.get(2).map(|a| a.as_str()).unwrap_or("")),
+            "puzzle" => puzzle_command(&args[2..]),
+            "analyze-batch" => analyze_batch_command(&args[2..]),
+            "bench" => bench_command(&args[2..]),
+            _ => println!(
+                "Unknown command: {} (try: play, tournament, replay <file>, serve [addr], http [addr], book, solve <moves>, puzzle, analyze-batch, bench)",
+                args[1]
+            ),
+        }
+        save_database();
+        return;
+    }
+
+    // 0 = human, 1 = random, 2 = randosmart, 3 = minimax, 4 = alphabeta
+    run_games(
+        &[0, 3],
+        TOTAL_GAMES as usize,
+        true,
+        &rules::connect(board::new_board(WIDTH, HEIGHT)),
+        0,
+    );
+    save_database();
+}
+
+// How often the servers write out what they've solved, they only stop
+// when they're killed
+const DB_SAVE_INTERVAL: time::Duration = time::Duration::from_secs(60);
+
+// Writes out the positions solved since the database (--db) was opened or
+// last saved. Anything can solve positions, the perfect level does it
+// every game.
+fn save_database() {
+    if let Err(e) = database::flush_global() {
+        eprintln!("Could not save to database: {}", e);
+    }
+}
+
+fn save_database_every(interval: time::Duration) {
+    thread::spawn(move || loop {
+        thread::sleep(interval);
+        save_database();
+    });
+}
+
+// Plays a lot of games on copies of start and reports how they went. Each
+// game opens with opening random moves, so bots that always answer the same
+// way still play different games.
+fn run_games(
+    player_types: &[i8],
+    games: usize,
+    print: bool,
+    start_rules: &dyn Rules,
+    opening: usize,
+) {
     // set a timer to find out how many games per second the computer can play
     let start = std::time::Instant::now();
     let mut game_number = 1;
@@ -19,11 +149,9 @@ fn main() {
     let mut player2_wins = 0;
     let mut ties = 0;
 
-    for _ in 0..TOTAL_GAMES as usize {
-        let winner: usize;
-
-        // 0 = human, 1 = random, 2 = randosmart, 3 = minimax
-        winner = game_handler(0, 3, true);
+    for _ in 0..games {
+        let game_rules = random_opening(start_rules, opening);
+        let winner: usize = game_handler(player_types, print, game_rules, &mut Vec::new());
 
         match winner {
             1 => player1_wins += 1,
@@ -43,24 +171,851 @@ fn main() {
     println!("{} milisecond elapsed", elapsed.as_millis());
     println!(
         "{} games per milisecond",
-        TOTAL_GAMES / elapsed.as_millis() as f32
+        games as f32 / elapsed.as_millis().max(1) as f32
     );
-    println!("{} games per second", TOTAL_GAMES / elapsed.as_secs_f32());
+    println!("{} games per second", games as f32 / elapsed.as_secs_f32());
     println!("{} games won by player 1", player1_wins);
     println!("{} games won by player 2", player2_wins);
     println!("{} games tied", ties);
 }
 
-fn game_handler(p1: usize, p2: usize, print: bool) -> usize {
-    let mut board: board::Board = board::new_board(WIDTH, HEIGHT);
-    let winner: usize;
+// A copy of start_rules with plies random moves played, none of which end
+// the game. Gives up and starts from start_rules if that keeps happening.
+fn random_opening(start_rules: &dyn Rules, plies: usize) -> Box<dyn Rules> {
+    for _ in 0..OPENING_TRIES {
+        let mut game_rules = start_rules.clone_rules();
+        for _ in 0..plies {
+            let player_move = engines::random_move(game_rules.as_ref());
+            game_rules.apply(player_move);
+            if game_rules.result() != 0 || game_rules.legal_moves().is_empty() {
+                break;
+            }
+        }
+        if game_rules.result() == 0 {
+            return game_rules;
+        }
+    }
+    println!("No {} move opening that keeps the game going", plies);
+    return start_rules.clone_rules();
+}
+
+// Random openings to try before giving up, see random_opening
+const OPENING_TRIES: usize = 1000;
+
+// tournament <player1> <player2> [games] [variant] [start options]
+//            [--opening <plies>]
+// Bots only. Plays the games without printing them and reports the results,
+// on any two player variant play knows. Every game starts from the same
+// position, see start_options, followed by --opening random moves if given.
+// That's how the difficulty levels were calibrated, see difficulty.rs.
+fn tournament_command(args: &[String]) {
+    let mut args = args.to_vec();
+    let start = match start_options(&mut args) {
+        Ok(start) => start,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let opening = match take_option(&mut args, "--opening").map(|v| v.map(|v| v.parse::<usize>())) {
+        Ok(None) => 0,
+        Ok(Some(Ok(plies))) => plies,
+        Ok(Some(Err(_))) => {
+            println!("--opening needs a number of moves");
+            return;
+        }
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let mut player_types = Vec::new();
+    for name in args.iter().take(2) {
+        match player::parse_player_type(name) {
+            Some(0) | None => {
+                println!("Not a bot: {}", name);
+                return;
+            }
+            Some(player_type) => player_types.push(player_type),
+        }
+    }
+    if player_types.len() < 2 {
+        println!("Usage: tournament <player1> <player2> [games] [variant]");
+        return;
+    }
+    let games: usize = args.get(2).and_then(|a| a.parse().ok()).unwrap_or(100);
+    let variant = args.get(3).map(|a| a.as_str()).unwrap_or("standard");
+    let mut start_rules = match variant_rules(variant, args.get(4..).unwrap_or(&[]), 2) {
+        Some(start_rules) => start_rules,
+        None => return,
+    };
+    if let Some(start) = start {
+        if let Err(e) = set_up_start(start_rules.as_mut(), &start) {
+            println!("Invalid starting position: {}", e);
+            return;
+        }
+    }
+    if let Some(start_board) = start_rules.board_mut() {
+        board::set_print(start_board, false);
+    }
+    run_games(&player_types, games, false, start_rules.as_ref(), opening);
+}
+
+// replay <file>
+// Plays a game record back move by move
+fn replay_command(args: &[String]) {
+    let path = match args.first() {
+        Some(path) => path,
+        None => {
+            println!("Usage: replay <file>");
+            return;
+        }
+    };
+    let game = match record::load(path) {
+        Ok(game) => game,
+        Err(e) => {
+            println!("Could not load {}: {}", path, e);
+            return;
+        }
+    };
+    let mut game_rules = match variant_rules(&game.variant, &game.variant_args, game.players.len())
+    {
+        Some(game_rules) => game_rules,
+        None => return,
+    };
+    let start = match start_from_list(&game.start) {
+        Ok(start) => start,
+        Err(e) => {
+            println!("Invalid record: {}", e);
+            return;
+        }
+    };
+    if let Some(start) = start {
+        if let Err(e) = set_up_start(game_rules.as_mut(), &start) {
+            println!("Invalid starting position: {}", e);
+            return;
+        }
+    }
+
+    println!("{} game: {}", game.variant, game.players.join(" vs "));
+    print!("{}", game_rules.render());
+    for (turn, text) in (1..).zip(game.moves.iter()) {
+        let piece = game_rules.to_move();
+        let player_move = match game_rules.parse_move(text) {
+            Some(player_move) if game_rules.apply(player_move) => player_move,
+            _ => {
+                println!("Move {} ({}) isn't legal here", turn, text);
+                return;
+            }
+        };
+        println!(
+            "Turn {}: player {} {}",
+            turn,
+            piece,
+            game_rules.describe_move(player_move)
+        );
+        print!("{}", game_rules.render());
+    }
+    let result = game_rules.result();
+    println!("Winner: {}", result);
+    if result != game.result {
+        println!("The record says the result was {}", game.result);
+    }
+}
+
+// Where a game starts when it doesn't start from the variant's usual
+// position
+struct StartOptions {
+    grid: Option<String>,
+    handicap: Option<String>,
+    to_move: Option<String>,
+    moves: Option<String>,
+}
+
+// Takes the starting position options out of the arguments:
+//   --grid <rows>      the position, top row first, e.g. ".../.X./OX."
+//                      (see board::setup_from_grid)
+//   --handicap <piece> an extra disc in the center column for that side
+//   --to-move <piece>  who moves first, otherwise whoever has the fewest
+//                      pieces once the grid and handicap are set up
+//   --start <moves>    a move string played from there, e.g. "4453"
+// Pieces are written as their symbol (X, O...) or number (1, 2...).
+// Returns None if none of them were given.
+fn start_options(args: &mut Vec<String>) -> Result<Option<StartOptions>, String> {
+    let start = start_options_from(|name| take_option(args, name))?;
+    return Ok(start);
+}
+
+// The start options as game records keep them, see record.rs
+fn start_list(start: &Option<StartOptions>) -> Vec<(String, String)> {
+    let mut list = Vec::new();
+    if let Some(start) = start {
+        let options = [
+            ("grid", &start.grid),
+            ("handicap", &start.handicap),
+            ("to-move", &start.to_move),
+            ("start", &start.moves),
+        ];
+        for (name, value) in options {
+            if let Some(value) = value {
+                list.push((name.to_string(), value.clone()));
+            }
+        }
+    }
+    return list;
+}
+
+fn start_from_list(list: &[(String, String)]) -> Result<Option<StartOptions>, String> {
+    if let Some((name, _)) = list
+        .iter()
+        .find(|(name, _)| !["grid", "handicap", "to-move", "start"].contains(&name.as_str()))
+    {
+        return Err(format!("unknown start option: {}", name));
+    }
+    return start_options_from(|name| {
+        let name = name.trim_start_matches("--");
+        return Ok(list
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.clone()));
+    });
+}
+
+// Builds the options from a lookup by option name ("--grid" and so on)
+fn start_options_from(
+    mut lookup: impl FnMut(&str) -> Result<Option<String>, String>,
+) -> Result<Option<StartOptions>, String> {
+    let start = StartOptions {
+        grid: lookup("--grid")?,
+        handicap: lookup("--handicap")?,
+        to_move: lookup("--to-move")?,
+        moves: lookup("--start")?,
+    };
+    if start.grid.is_none()
+        && start.handicap.is_none()
+        && start.to_move.is_none()
+        && start.moves.is_none()
+    {
+        return Ok(None);
+    }
+    return Ok(Some(start));
+}
+
+fn set_up_start(game_rules: &mut dyn Rules, start: &StartOptions) -> Result<(), String> {
+    let board = match game_rules.board_mut() {
+        Some(board) => board,
+        None => return Err("this variant always starts from its usual position".to_string()),
+    };
+    if let Some(grid) = &start.grid {
+        board::setup_from_grid(board, grid)?;
+    }
+    if let Some(handicap) = &start.handicap {
+        let piece = parse_piece(handicap, board)?;
+        if !board::add_handicap(board, piece) {
+            return Err("no room for a handicap disc in the center column".to_string());
+        }
+        board::infer_next_piece(board);
+    }
+    if let Some(to_move) = &start.to_move {
+        let piece = parse_piece(to_move, board)?;
+        board::set_next_piece(board, piece);
+    }
+    if let Some(moves) = &start.moves {
+        board::apply_moves(board, moves)?;
+    }
+    return Ok(());
+}
 
+// "X", "o" or "1" style piece names
+fn parse_piece(name: &str, board: &board::Board) -> Result<usize, String> {
+    let players = board::players(board);
+    if let Ok(piece) = name.parse::<usize>() {
+        if piece >= 1 && piece <= players {
+            return Ok(piece);
+        }
+    }
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if let Some(i) = board::SYMBOLS[..players]
+            .iter()
+            .position(|s| s.eq_ignore_ascii_case(&c))
+        {
+            return Ok(i + 1);
+        }
+    }
+    return Err(format!("unknown piece: {}", name));
+}
+
+// Pulls "--name value" out of the arguments
+fn take_option(args: &mut Vec<String>, name: &str) -> Result<Option<String>, String> {
+    match args.iter().position(|a| a == name) {
+        Some(i) => {
+            if i + 1 >= args.len() {
+                return Err(format!("{} needs a value", name));
+            }
+            let value = args.remove(i + 1);
+            args.remove(i);
+            return Ok(Some(value));
+        }
+        None => return Ok(None),
+    }
+}
+
+// Solves a position all the way to the end. With --db the result (and
+// everything solved along the way) is saved for next time.
+fn solve_command(moves: &str) {
+    let position = match board::from_move_string(moves, WIDTH, HEIGHT) {
+        Ok(position) => position,
+        Err(e) => {
+            println!("Invalid position: {}", e);
+            return;
+        }
+    };
+    if board::get_empty_columns(&position).is_empty() {
+        println!("Board is full");
+        return;
+    }
+    let start = std::time::Instant::now();
+    let result = search::solve(&position);
+    let elapsed = start.elapsed();
+    if let Err(e) = database::flush_global() {
+        println!("Could not save to database: {}", e);
+    }
+
+    if result.score == 0 {
+        println!("Result: draw");
+    } else {
+        println!("Result: {}", search::describe_score(result.score));
+    }
+    println!("Best move: {}", result.best_move + 1);
+    let values: Vec<String> = result
+        .column_values
+        .iter()
+        .map(|v| match v {
+            Some(score) => score.to_string(),
+            None => "-".to_string(),
+        })
+        .collect();
+    println!("Values: {}", values.join(" "));
+    println!(
+        "Nodes: {} in {:.3}s",
+        result.stats.nodes,
+        elapsed.as_secs_f32()
+    );
+    println!("Search: {}", stats::summary(&result.stats));
+}
+
+// Standard board positions for bench, as 1-based move strings: openings,
+// middlegames and endgames, none with a win on the spot
+const BENCH_POSITIONS: [&str; 10] = [
+    "",
+    "4",
+    "4453",
+    "326413",
+    "714245541",
+    "245353244546",
+    "466664242225265",
+    "353561511163343242",
+    "5376131256573125735564",
+    "245463525554576643434637622376",
+];
+
+// Total nodes alphabeta searches on BENCH_POSITIONS at BENCH_DEPTH. Any
+// change to the search or the evaluation that isn't meant to change what it
+// searches should leave this alone, and one that is meant to updates it in
+// the same commit. Only holds without --db, solved positions cut the search.
+const BENCH_DEPTH: i32 = 10;
+const BENCH_SIGNATURE: u64 = 1966062;
+
+// bench [engine] [depth]
+// Searches every BENCH_POSITIONS position with one engine to a fixed depth
+// and prints what the search did for each one and for all of them together
+// (see stats.rs). Engines are alphabeta (search.rs, the default), generic,
+// paranoid, maxn and mcts, whose depth is its number of iterations.
+//
+// Like a chess engine's bench, plain bench is a quick check that nothing
+// changed by accident: it ends with the node signature and the time, and
+// exits with an error if the signature isn't BENCH_SIGNATURE.
+fn bench_command(args: &[String]) {
+    let engine = args.first().map(|a| a.as_str()).unwrap_or("alphabeta");
+    let default_depth = match engine {
+        "alphabeta" => BENCH_DEPTH,
+        "generic" | "paranoid" => 6,
+        "maxn" => 5,
+        "mcts" => 2000,
+        _ => {
+            println!(
+                "Unknown engine: {} (try alphabeta, generic, paranoid, maxn or mcts)",
+                engine
+            );
+            return;
+        }
+    };
+    let depth = match args.get(1).map(|d| d.parse::<i32>()) {
+        None => default_depth,
+        Some(Ok(depth)) if depth > 0 => depth,
+        Some(_) => {
+            println!("Usage: bench [engine] [depth]");
+            return;
+        }
+    };
+
+    println!("Bench: {} to depth {}", engine, depth);
+    let mut total = stats::new_stats(depth);
+    for moves in BENCH_POSITIONS {
+        let mut position = board::from_move_string(moves, WIDTH, HEIGHT).unwrap();
+        board::set_print(&mut position, false);
+        let (column, result) = match engine {
+            "alphabeta" => {
+                let result = search::search(&position, depth);
+                (result.best_move, result.stats)
+            }
+            "generic" => bench_move(engines::alphabeta_search(&rules::connect(position), depth)),
+            "paranoid" => bench_move(multisearch::paranoid_search(&position, depth)),
+            "maxn" => bench_move(multisearch::maxn_search(&position, depth)),
+            _ => bench_move(engines::mcts_search(
+                &rules::connect(position),
+                depth as usize,
+            )),
+        };
+        println!(
+            "{:<34} {}  {}",
+            format!("{:?}", moves),
+            column + 1,
+            stats::summary(&result)
+        );
+        stats::add(&mut total, &result);
+    }
+    println!("{:<34} -  {}", "Total", stats::summary(&total));
+    println!();
+    println!("Signature: {}", total.nodes);
+    println!(
+        "Time: {:.0} ms ({:.0} nps)",
+        total.time_ms,
+        stats::nodes_per_second(&total)
+    );
+
+    if engine == "alphabeta" && depth == BENCH_DEPTH {
+        if total.nodes != BENCH_SIGNATURE {
+            println!("Signature changed, expected {}", BENCH_SIGNATURE);
+            std::process::exit(1);
+        }
+        println!("Signature matches");
+    }
+}
+
+fn bench_move(
+    (player_move, result): (board::Move, stats::SearchStats),
+) -> (usize, stats::SearchStats) {
+    return match player_move {
+        board::Move::Drop(column) | board::Move::Pop(column) | board::Move::Place(_, column) => {
+            (column, result)
+        }
+    };
+}
+
+// analyze-batch <file> [output] [--format csv|json] [--depth <plies>]
+// Solves every position in file (one move string per line) on all cores and
+// writes the score, best moves, nodes and time for each, see batch.rs. The
+// format follows the output file's extension unless --format says, CSV by
+// default, and without an output file it goes to stdout. --depth searches
+// that far instead of solving, for sets too big to solve.
+fn analyze_batch_command(args: &[String]) {
+    let mut args = args.to_vec();
+    let format = match take_option(&mut args, "--format") {
+        Ok(format) => format,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let depth = match take_option(&mut args, "--depth").map(|d| d.map(|d| d.parse::<i32>())) {
+        Ok(None) => None,
+        Ok(Some(Ok(depth))) if depth > 0 => Some(depth),
+        Ok(Some(_)) => {
+            println!("--depth needs a number of plies");
+            return;
+        }
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let Some(path) = args.first() else {
+        println!("Usage: analyze-batch <file> [output] [--format csv|json] [--depth <plies>]");
+        return;
+    };
+    let output = args.get(1);
+    let json = match format.as_deref() {
+        Some("json") => true,
+        Some("csv") => false,
+        Some(other) => {
+            println!("Unknown format: {} (try csv or json)", other);
+            return;
+        }
+        None => output.is_some_and(|o| o.ends_with(".json")),
+    };
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            println!("Could not read {}: {}", path, e);
+            return;
+        }
+    };
+
+    let start = std::time::Instant::now();
+    let rows = batch::analyze(&text, WIDTH, HEIGHT, depth);
+    let elapsed = start.elapsed();
+    if let Err(e) = database::flush_global() {
+        eprintln!("Could not save to database: {}", e);
+    }
+    let out = if json {
+        batch::to_json(&rows)
+    } else {
+        batch::to_csv(&rows)
+    };
+    match output {
+        Some(output) => {
+            if let Err(e) = fs::write(output, out) {
+                println!("Could not write {}: {}", output, e);
+                return;
+            }
+        }
+        None => print!("{}", out),
+    }
+    // The summary goes to stderr so stdout stays a clean CSV or JSON file
+    let nodes: u64 = rows.iter().map(|row| row.nodes).sum();
+    let errors = rows.iter().filter(|row| row.error.is_some()).count();
+    eprintln!(
+        "{} positions ({} invalid), {} nodes in {:.2}s, {:.0} nodes/s",
+        rows.len(),
+        errors,
+        nodes,
+        elapsed.as_secs_f32(),
+        nodes as f64 / elapsed.as_secs_f64().max(1e-9)
+    );
+}
+
+// puzzle generate <count> [win in] [file]
+// puzzle train [file] [first]
+// generate finds count new "win in N" puzzles and adds them to file
+// (puzzles.jsonl by default), skipping any the file already has. win in is
+// a number of moves or a range like 2-4, which is the default. train plays
+// through the file's puzzles, from puzzle number first on, see trainer.rs.
+fn puzzle_command(args: &[String]) {
+    if args.first().map(|a| a.as_str()) == Some("train") {
+        let path = args
+            .get(1)
+            .map(|a| a.as_str())
+            .unwrap_or(DEFAULT_PUZZLE_FILE);
+        let first: usize = args.get(2).and_then(|a| a.parse().ok()).unwrap_or(1);
+        match puzzle::load(path) {
+            Ok(puzzles) if first >= 1 && first <= puzzles.len() => {
+                trainer::train(&puzzles, first, WIDTH, HEIGHT);
+            }
+            Ok(puzzles) => println!("{} has {} puzzles", path, puzzles.len()),
+            Err(e) => println!("Could not load {}: {}", path, e),
+        }
+        return;
+    }
+    match (
+        args.first().map(|a| a.as_str()),
+        args.get(1).and_then(|a| a.parse::<usize>().ok()),
+    ) {
+        (Some("generate"), Some(count)) => {
+            let range = args.get(2).map(|a| a.as_str()).unwrap_or(DEFAULT_WIN_IN);
+            let (min_n, max_n) = match parse_win_in(range) {
+                Some(range) => range,
+                None => {
+                    println!("Invalid win in: {} (try 3 or 2-4)", range);
+                    return;
+                }
+            };
+            let path = args
+                .get(3)
+                .map(|a| a.as_str())
+                .unwrap_or(DEFAULT_PUZZLE_FILE);
+            let mut puzzles = match puzzle::load(path) {
+                Ok(puzzles) => puzzles,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+                Err(e) => {
+                    println!("Could not load {}: {}", path, e);
+                    return;
+                }
+            };
+            let mut seen = std::collections::HashSet::new();
+            for existing in puzzles.iter() {
+                if let Ok(position) = board::from_move_string(&existing.moves, WIDTH, HEIGHT) {
+                    seen.insert(board::canonical_key(&position).0);
+                }
+            }
+            let start = std::time::Instant::now();
+            let found = puzzle::generate(count, min_n, max_n, WIDTH, HEIGHT, &mut seen);
+            puzzles.extend(found);
+            match puzzle::save(&puzzles, path) {
+                Ok(()) => println!(
+                    "Saved {} puzzles to {} ({} new) in {:.1}s",
+                    puzzles.len(),
+                    path,
+                    count,
+                    start.elapsed().as_secs_f32()
+                ),
+                Err(e) => println!("Could not save {}: {}", path, e),
+            }
+        }
+        _ => {
+            println!("Usage: puzzle generate <count> [win in] [file] | puzzle train [file] [first]")
+        }
+    }
+}
+
+const DEFAULT_WIN_IN: &str = "2-4";
+const DEFAULT_PUZZLE_FILE: &str = "puzzles.jsonl";
+
+// "3" or "2-4", moves to win
+fn parse_win_in(text: &str) -> Option<(usize, usize)> {
+    let (min, max) = match text.split_once('-') {
+        Some((min, max)) => (min.parse().ok()?, max.parse().ok()?),
+        None => (text.parse().ok()?, text.parse().ok()?),
+    };
+    if min == 0 || min > max {
+        return None;
+    }
+    return Some((min, max));
+}
+
+// book generate <file> [plies] [depth]
+// book probe <file> <moves>
+fn book_command(args: &[String]) {
+    match args.first().map(|a| a.as_str()) {
+        Some("generate") if args.len() >= 2 => {
+            let plies: usize = args.get(2).and_then(|a| a.parse().ok()).unwrap_or(6);
+            let depth: i32 = args.get(3).and_then(|a| a.parse().ok()).unwrap_or(10);
+            let start = std::time::Instant::now();
+            let generated = book::generate(WIDTH, HEIGHT, plies, depth);
+            println!(
+                "Generated {} in {:.1}s",
+                book::describe(&generated),
+                start.elapsed().as_secs_f32()
+            );
+            if let Err(e) = book::save(&generated, &args[1]) {
+                println!("Could not save book: {}", e);
+            }
+        }
+        Some("probe") if args.len() >= 2 => {
+            let loaded = match book::load(&args[1]) {
+                Ok(loaded) => loaded,
+                Err(e) => {
+                    println!("Could not load book: {}", e);
+                    return;
+                }
+            };
+            let moves = args.get(2).map(|a| a.as_str()).unwrap_or("");
+            let position = match board::from_move_string(moves, WIDTH, HEIGHT) {
+                Ok(position) => position,
+                Err(e) => {
+                    println!("Invalid position: {}", e);
+                    return;
+                }
+            };
+            match book::probe(&loaded, &position) {
+                Some(result) => {
+                    println!("Best move: {}", result.best_move + 1);
+                    println!("Score: {}", result.score);
+                    println!("Values: {:?}", result.column_values);
+                }
+                None => println!("Position is not in the book"),
+            }
+        }
+        _ => println!("Usage: book generate <file> [plies] [depth] | book probe <file> <moves>"),
+    }
+}
+
+// play [player1] [player2] [more players...] [variant] [start options]
+// Players are human, random, randosmart, minimax, alphabeta, maxn,
+// paranoid, mcts or a difficulty level (beginner, easy, medium, hard,
+// expert or perfect, see difficulty.rs), the variant is standard, popout, popten, five (5-in-a-Row),
+// cylinder (lines wrap around the sides of the board), misere (completing a
+// line loses), map <file> (a board with obstacles, see board::from_map and
+// maps/), one of the gravity-free games: tictactoe, gomoku (15x15, five in
+// a row) or mnk <width> <height> <k>, or multi [width] [height] for three or
+// more players (9x7 by default). See start_options for starting somewhere other
+// than the variant's usual start.
+// --record <file> saves the game for replay afterwards.
+fn play_command(args: &[String]) {
+    let mut args = args.to_vec();
+    let start = match start_options(&mut args) {
+        Ok(start) => start,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    let record_path = match take_option(&mut args, "--record") {
+        Ok(path) => path,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+    // Everything up to the first word that isn't a player type is a player
+    let mut player_types: Vec<i8> = args
+        .iter()
+        .map_while(|name| player::parse_player_type(name))
+        .collect();
+    let variant_index = player_types.len();
+    let defaults = [0, 3];
+    if player_types.len() < 2 {
+        player_types.extend_from_slice(&defaults[player_types.len()..]);
+    }
+    let variant = args
+        .get(variant_index)
+        .map(|a| a.as_str())
+        .unwrap_or("standard");
+    let variant_args = args.get(variant_index + 1..).unwrap_or(&[]);
+    let mut game_rules = match variant_rules(variant, variant_args, player_types.len()) {
+        Some(game_rules) => game_rules,
+        None => return,
+    };
+    if game_rules.players() != player_types.len() {
+        println!("{} is a game for {} players", variant, game_rules.players());
+        return;
+    }
+    if let Some(start) = &start {
+        if let Err(e) = set_up_start(game_rules.as_mut(), start) {
+            println!("Invalid starting position: {}", e);
+            return;
+        }
+    }
+    if let (true, Some(help)) = (player_types.contains(&0), game_rules.input_help()) {
+        println!("{}", help);
+    }
+    if player_types.contains(&0) && game_rules.board().is_some() {
+        println!("Type hint to see the threats in the position");
+    }
+    let mut played = Vec::new();
+    let winner = game_handler(&player_types, true, game_rules.clone_rules(), &mut played);
+
+    if let Some(path) = record_path {
+        let game = record::Record {
+            variant: variant.to_string(),
+            variant_args: variant_args.to_vec(),
+            players: player_types
+                .iter()
+                .map(|t| player::player_type_name(*t).to_string())
+                .collect(),
+            start: start_list(&start),
+            result: winner,
+            moves: record_moves(game_rules.as_mut(), &played),
+        };
+        match record::save(&game, &path) {
+            Ok(()) => println!("Saved the game to {}", path),
+            Err(e) => println!("Could not save the game: {}", e),
+        }
+    }
+}
+
+// Writes moves in the rule set's notation, playing them on the way since
+// what a move looks like can depend on the position (Pop Ten's phases)
+fn record_moves(game_rules: &mut dyn Rules, played: &[board::Move]) -> Vec<String> {
+    let mut moves = Vec::new();
+    for player_move in played {
+        moves.push(game_rules.move_to_string(*player_move));
+        game_rules.apply(*player_move);
+    }
+    return moves;
+}
+
+// Builds the rule set for a variant, or prints what's wrong and returns
+// None
+fn variant_rules(variant: &str, variant_args: &[String], players: usize) -> Option<Box<dyn Rules>> {
+    if variant == "popten" {
+        return Some(Box::new(popten::new_game(WIDTH, HEIGHT)));
+    }
+    let board = variant_board(variant, variant_args, players)?;
+    return Some(Box::new(rules::connect(board)));
+}
+
+// Builds the starting board for the variants that are played on a plain
+// Board, or prints what's wrong and returns None
+fn variant_board(variant: &str, variant_args: &[String], players: usize) -> Option<board::Board> {
+    let board = match variant {
+        "standard" => board::new_board(WIDTH, HEIGHT),
+        "popout" => board::new_popout_board(WIDTH, HEIGHT),
+        "five" => board::new_five_board(),
+        "cylinder" => board::new_cylinder_board(WIDTH, HEIGHT),
+        "misere" => board::new_misere_board(WIDTH, HEIGHT),
+        "map" => {
+            let path = match variant_args.first() {
+                Some(path) => path,
+                None => {
+                    println!("Usage: play <player1> <player2> map <file>");
+                    return None;
+                }
+            };
+            let loaded = fs::read_to_string(path)
+                .map_err(|e| e.to_string())
+                .and_then(|text| board::from_map(&text));
+            match loaded {
+                Ok(board) => board,
+                Err(e) => {
+                    println!("Could not load map {}: {}", path, e);
+                    return None;
+                }
+            }
+        }
+        "tictactoe" => board::new_mnk_board(3, 3, 3),
+        "gomoku" => board::new_mnk_board(15, 15, 5),
+        "mnk" => {
+            let sizes: Vec<usize> = variant_args.iter().filter_map(|a| a.parse().ok()).collect();
+            match sizes[..] {
+                [width, height, k] if width > 0 && height > 0 && k > 0 => {
+                    board::new_mnk_board(width, height, k)
+                }
+                _ => {
+                    println!("Usage: play <player1> <player2> mnk <width> <height> <k>");
+                    return None;
+                }
+            }
+        }
+        "multi" => {
+            let sizes: Vec<usize> = variant_args.iter().filter_map(|a| a.parse().ok()).collect();
+            let width = sizes.first().copied().unwrap_or(9);
+            let height = sizes.get(1).copied().unwrap_or(7);
+            if players > board::SYMBOLS.len() || width < 4 || height < 4 {
+                println!(
+                    "Usage: play <player1> <player2> <player3> [up to {} players] multi [width] [height]",
+                    board::SYMBOLS.len()
+                );
+                return None;
+            }
+            board::new_multiplayer_board(width, height, players)
+        }
+        variant => {
+            println!(
+                "Unknown variant: {} (try: standard, popout, popten, five, cylinder, misere, map, tictactoe, gomoku, mnk, multi)",
+                variant
+            );
+            return None;
+        }
+    };
+    return Some(board);
+}
+
+// One player type per player, in turn order. The moves played end up in
+// played.
+fn game_handler(
+    player_types: &[i8],
+    print: bool,
+    mut game_rules: Box<dyn Rules>,
+    played: &mut Vec<board::Move>,
+) -> usize {
     if print {
-        board::print_board(&mut board);
+        print!("{}", game_rules.render());
     }
 
     // player types: 1 = player, 2 = random, 3 = randosmart
-    winner = game_loop(&mut board, print, p1 as i8, p2 as i8);
+    let winner: usize = game_loop(game_rules.as_mut(), print, player_types, played);
 
     if print {
         println!("Winner: {}", winner);
@@ -69,58 +1024,78 @@ fn game_handler(p1: usize, p2: usize, print: bool) -> usize {
     return winner;
 }
 
-fn game_loop(board: &mut board::Board, print: bool, p1_type: i8, p2_type: i8) -> usize {
+fn game_loop(
+    game_rules: &mut dyn Rules,
+    print: bool,
+    player_types: &[i8],
+    played: &mut Vec<board::Move>,
+) -> usize {
     // Tracks turn so we know which player's turn it is
     let mut turn: i32 = 1;
     // Tracks winner
     let mut winner: usize;
     // Creates a player object for each player
-    // player_type: 0 = human, 1 = random, 2 = randosmart, 3 = minimax
-    let player1 = player::new_player(p1_type, 1);
-    let player2 = player::new_player(p2_type, 2);
+    // player_type: 0 = human, 1 = random, 2 = randosmart, 3 = minimax, 4 = alphabeta,
+    // 5 = maxn, 6 = paranoid, 7 = mcts, 8 and up = difficulty levels
+    let players: Vec<player::Player> = (1..)
+        .zip(player_types.iter())
+        .map(|(piece, player_type)| player::new_player(*player_type, piece))
+        .collect();
     // Tracks most recent move
-    let mut player_move: usize;
-    let mut rng = thread_rng();
+    let mut player_move: board::Move;
 
     // Game loop that only breaks upon tie or win
     loop {
-        // Gets the player piece based on turn
-        let player_piece: usize = board::get_player_piece(turn);
-        // If turn is odd, it's player 1's turn
-        if player_piece == 1 {
-            // player_move = player::get_move(&player1, &mut board::clone_board(board), &mut rng);
-            player_move = player::get_move(&player1, &mut board::clone_board(board));
+        // Gets the player piece from the rules
+        let player_piece: usize = game_rules.to_move();
+        player_move = player::get_rules_move(&players[player_piece - 1], game_rules);
+        // Why the bots played what they did, worked out before the move
+        // changes the position
+        let explanation = if print && player_types[player_piece - 1] != 0 {
+            explain::explain_text(game_rules, player_move)
         } else {
-            // player_move = player::get_move(&player2, &mut board::clone_board(board), &mut rng);
-            player_move = player::get_move(&player2, &mut board::clone_board(board));
-        }
+            None
+        };
 
         // Makes the move and checks if it was valid
-        let player_move_result: bool = board::add_piece(board, player_move, player_piece);
+        let player_move_result: bool = game_rules.apply(player_move);
         // If it was valid, we increment the turn and print the board if print is true
         if player_move_result {
-            turn = turn + 1;
+            turn += 1;
+            played.push(player_move);
             if print {
                 println!("Turn: {turn}");
-                println!(
-                    "Player {} moved to column {}",
-                    player_piece,
-                    player_move + 1
-                );
-                board::print_board(board);
+                match &explanation {
+                    Some(reasons) => println!(
+                        "Player {} {} ({})",
+                        player_piece,
+                        game_rules.describe_move(player_move),
+                        reasons
+                    ),
+                    None => println!(
+                        "Player {} {}",
+                        player_piece,
+                        game_rules.describe_move(player_move)
+                    ),
+                }
+                print!("{}", game_rules.render());
             }
         } else {
             if !print {
-                println!("Invalid move: {}", player_move);
+                println!("Invalid move: {:?}", player_move);
                 // panic!("Invalid move");
             }
         }
         // Checks if the game is over
-        winner = is_game_over(board, turn);
-        // 0 = no winner yet, 1 = player 1 wins, 2 = player 2 wins, 3 = tie
+        winner = game_rules.result();
+        // 0 = no winner yet, otherwise the winning piece, or one past the
+        // last piece (3 with two players) for a tie
         if winner == 0 {
-            // Delay for 1 second
-            thread::sleep(time::Duration::from_millis(200));
+            // Slow down so people can follow along, no need when nobody's
+            // watching
+            if print {
+                thread::sleep(time::Duration::from_millis(200));
+            }
             continue;
         } else {
             // println!("Winner: {}", winner);
@@ -129,26 +1104,3 @@ fn game_loop(board: &mut board::Board, print: bool, p1_type: i8, p2_type: i8) ->
     }
     return winner;
 }
-
-//0 is not game over, 1 red won, 2 yellow won, 3 tie
-fn is_game_over(board: &mut board::Board, turn: i32) -> usize {
-    let mut game_over_status: usize = 0;
-    if turn > 42 {
-        game_over_status = 3;
-        //println!("TIE");
-        return game_over_status;
-    } else {
-        let win: bool = board::game_over_check(board);
-        if win {
-            // Because turn is incremented before this function is called, we need to subtract 1
-            if (turn - 1) % 2 == 1 {
-                game_over_status = 1;
-                //println!("RED WON");
-            } else {
-                game_over_status = 2;
-                //println!("YELLOW WON");
-            }
-        }
-    }
-    return game_over_status;
-}
@@ -0,0 +1,280 @@
+// Searches for games with more than two players. Negamax relies on one
+// player's gain being the other's loss, which stops being true once there
+// is a third player.
+//
+// Max-n gives every player their own score and assumes each player picks
+// the move that is best for themselves. Paranoid assumes everyone else is
+// out to get the player searching, which turns the game back into a two
+// sided one, so alpha-beta cutoffs work and it can look deeper.
+//
+// Both go through board::legal_moves and rules::board_result, so they play
+// any board variant with any number of players.
+use crate::board;
+use crate::board::{Board, Move};
+use crate::rules;
+use crate::search;
+use crate::search::WIN_SCORE;
+use crate::stats;
+use crate::stats::SearchStats;
+use std::time::Instant;
+
+// Moves closest to the center column first, like search::ordered_moves
+fn ordered_moves(board: &Board) -> Vec<Move> {
+    let mut moves = board::legal_moves(board);
+    let center = (board::width(board) as isize - 1) / 2;
+    moves.sort_by_key(|m| {
+        let column = match m {
+            Move::Drop(col) | Move::Pop(col) | Move::Place(_, col) => *col as isize,
+        };
+        return (column - center).abs();
+    });
+    return moves;
+}
+
+pub fn maxn_move(board: &Board, depth: i32) -> Move {
+    return maxn_search(board, depth).0;
+}
+
+// maxn_move, along with the search's counters
+pub fn maxn_search(board: &Board, depth: i32) -> (Move, SearchStats) {
+    let start = Instant::now();
+    let mut stats = stats::new_stats(depth);
+    let mut t_board = board::clone_board(board);
+    board::set_print(&mut t_board, false);
+    let piece = board::next_piece(board);
+
+    let mut best_move = None;
+    let mut best_score = i32::MIN;
+    for player_move in ordered_moves(board) {
+        board::make_move(&mut t_board, player_move, piece);
+        let scores = match end_scores(&mut t_board, 1) {
+            Some(scores) => scores,
+            None => maxn(&mut t_board, depth - 1, 1, &mut stats),
+        };
+        board::undo_move(&mut t_board);
+        if scores[piece - 1] > best_score {
+            best_score = scores[piece - 1];
+            best_move = Some(player_move);
+        }
+    }
+    stats::finish(&mut stats, start);
+    return (best_move.unwrap_or(Move::Drop(0)), stats);
+}
+
+// Returns a score for every player, scores[0] is player 1's
+fn maxn(board: &mut Board, depth: i32, ply: i32, stats: &mut SearchStats) -> Vec<i32> {
+    stats::node(stats, ply);
+    if depth <= 0 {
+        return evaluate_all(board);
+    }
+    let moves = ordered_moves(board);
+    // Nowhere left to play, it's a tie
+    if moves.is_empty() {
+        return vec![0; board::players(board)];
+    }
+
+    let piece = board::next_piece(board);
+    let mut best: Option<Vec<i32>> = None;
+    stats::expanded(stats, moves.len());
+    for (index, player_move) in moves.into_iter().enumerate() {
+        board::make_move(board, player_move, piece);
+        let scores = match end_scores(board, ply + 1) {
+            Some(scores) => scores,
+            None => maxn(board, depth - 1, ply + 1, stats),
+        };
+        board::undo_move(board);
+        // Winning right now is as good as it gets
+        if scores[piece - 1] > WIN_SCORE - ply - 2 {
+            stats::cutoff(stats, index);
+            return scores;
+        }
+        if best
+            .as_ref()
+            .is_none_or(|b| scores[piece - 1] > b[piece - 1])
+        {
+            best = Some(scores);
+        }
+    }
+    return best.unwrap();
+}
+
+pub fn paranoid_move(board: &Board, depth: i32) -> Move {
+    return paranoid_search(board, depth).0;
+}
+
+// paranoid_move, along with the search's counters
+pub fn paranoid_search(board: &Board, depth: i32) -> (Move, SearchStats) {
+    let start = Instant::now();
+    let mut stats = stats::new_stats(depth);
+    let mut t_board = board::clone_board(board);
+    board::set_print(&mut t_board, false);
+    let piece = board::next_piece(board);
+
+    let mut best_move = None;
+    let mut best_score = i32::MIN;
+    for player_move in ordered_moves(board) {
+        board::make_move(&mut t_board, player_move, piece);
+        let score = match end_scores(&mut t_board, 1) {
+            Some(scores) => scores[piece - 1],
+            None => paranoid(
+                &mut t_board,
+                depth - 1,
+                best_score,
+                i32::MAX,
+                piece,
+                1,
+                &mut stats,
+            ),
+        };
+        board::undo_move(&mut t_board);
+        if score > best_score {
+            best_score = score;
+            best_move = Some(player_move);
+        }
+    }
+    stats::finish(&mut stats, start);
+    return (best_move.unwrap_or(Move::Drop(0)), stats);
+}
+
+// Score for root, which is trying to maximize it while every other player
+// is trying to minimize it
+#[allow(clippy::too_many_arguments)]
+fn paranoid(
+    board: &mut Board,
+    depth: i32,
+    mut alpha: i32,
+    mut beta: i32,
+    root: usize,
+    ply: i32,
+    stats: &mut SearchStats,
+) -> i32 {
+    stats::node(stats, ply);
+    if depth <= 0 {
+        return evaluate_all(board)[root - 1];
+    }
+    let moves = ordered_moves(board);
+    if moves.is_empty() {
+        return 0;
+    }
+
+    let piece = board::next_piece(board);
+    let maximizing = piece == root;
+    let mut best_score = if maximizing { i32::MIN } else { i32::MAX };
+    let mut searched = 0;
+    for (index, player_move) in moves.into_iter().enumerate() {
+        searched += 1;
+        board::make_move(board, player_move, piece);
+        let score = match end_scores(board, ply + 1) {
+            Some(scores) => scores[root - 1],
+            None => paranoid(board, depth - 1, alpha, beta, root, ply + 1, stats),
+        };
+        board::undo_move(board);
+
+        if maximizing {
+            best_score = best_score.max(score);
+            alpha = alpha.max(score);
+        } else {
+            best_score = best_score.min(score);
+            beta = beta.min(score);
+        }
+        if alpha >= beta {
+            stats::cutoff(stats, index);
+            break;
+        }
+    }
+    stats::expanded(stats, searched);
+    return best_score;
+}
+
+// Scores for every player if the game is over after the last move, None
+// if it goes on. The winner scores a win, everyone else a loss, sooner
+// counts for more, and a draw is 0 for everyone. A pop can complete lines
+// for anyone, not just the player who moved, so this goes by
+// rules::board_result rather than game_over_check.
+fn end_scores(board: &mut Board, ply: i32) -> Option<Vec<i32>> {
+    let result = rules::board_result(board);
+    if result == 0 {
+        return None;
+    }
+    if result == board::tie_result(board) {
+        return Some(vec![0; board::players(board)]);
+    }
+    let scores = (1..=board::players(board))
+        .map(|piece| {
+            if piece == result {
+                WIN_SCORE - ply
+            } else {
+                -(WIN_SCORE - ply)
+            }
+        })
+        .collect();
+    return Some(scores);
+}
+
+// Each player's own windows (see search::evaluate) minus everyone else's,
+// the other way round under misère rules
+pub fn evaluate_all(board: &Board) -> Vec<i32> {
+    let players = board::players(board);
+    let mut own = vec![0; players];
+    search::for_each_window(board, |cells| {
+        let mut owner = 0;
+        let mut count = 0;
+        for cell in cells {
+            if *cell == 0 {
+                continue;
+            }
+            if (owner != 0 && *cell != owner) || !board::is_piece(board, *cell) {
+                // Nobody can win through a window two players (or an
+                // obstacle) are in
+                return;
+            }
+            owner = *cell;
+            count += 1;
+        }
+        if owner != 0 {
+            own[owner - 1] += search::window_score(count, 0, cells.len() as i32);
+        }
+    });
+    let total: i32 = own.iter().sum();
+    let sign = if board::is_misere(board) { -1 } else { 1 };
+    return own
+        .iter()
+        .map(|score| sign * (score - (total - score)))
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // X to move. Popping X out of the first column drops an O into the
+    // bottom row and makes four for O, so the only move that doesn't lose
+    // is blocking O in the fifth column.
+    fn pop_trap() -> Board {
+        let mut board = board::new_popout_board(7, 6);
+        board::set_print(&mut board, false);
+        board::setup_from_grid(
+            &mut board,
+            "......./......./......./......./O....../XOOO...",
+        )
+        .unwrap();
+        return board;
+    }
+
+    #[test]
+    fn a_pop_can_win_for_the_other_player() {
+        let mut board = pop_trap();
+        assert!(board::make_move(&mut board, Move::Pop(0), 1));
+        assert_eq!(
+            end_scores(&mut board, 1),
+            Some(vec![-(WIN_SCORE - 1), WIN_SCORE - 1])
+        );
+    }
+
+    #[test]
+    fn searches_see_through_pops() {
+        let board = pop_trap();
+        assert_eq!(maxn_move(&board, 2), Move::Drop(4));
+        assert_eq!(paranoid_move(&board, 4), Move::Drop(4));
+    }
+}
@@ -1,13 +1,25 @@
 use crate::board;
-use crate::board::Board;
+use crate::board::{Board, Move};
+use crate::book;
+use crate::database;
+use crate::difficulty;
+use crate::engines;
+use crate::multisearch;
+use crate::rules;
+use crate::rules::Rules;
+use crate::search;
+use crate::threats;
 use rand::{seq::SliceRandom, thread_rng};
 use std::io;
 
 #[derive(Copy, Clone)]
 pub struct Player {
-    // 0 = human, 1 = random, 2 = randosmart, 3 = minimax
+    // 0 = human, 1 = random, 2 = randosmart, 3 = minimax, 4 = alphabeta,
+    // 5 = maxn, 6 = paranoid, 7 = mcts, then the difficulty levels from
+    // difficulty::FIRST_LEVEL_TYPE on
     player_type: i8,
-    // 1 = red "X" (first), 2 = yellow "O" (second)
+    // 1 = red "X" (first), 2 = yellow "O" (second), then the other
+    // board::SYMBOLS in games with more players
     player_piece: usize,
 }
 
@@ -18,24 +30,175 @@ pub fn new_player(player_type: i8, player_piece: usize) -> Player {
     }
 }
 
-pub fn get_move(player: &Player, board: &mut Board) -> usize {
+// Maps a player type name (as typed on the command line or sent by a client)
+// to its player_type number
+pub fn parse_player_type(name: &str) -> Option<i8> {
+    match name.to_lowercase().as_str() {
+        "human" => Some(0),
+        "random" => Some(1),
+        "randosmart" => Some(2),
+        "minimax" => Some(3),
+        "alphabeta" => Some(4),
+        "maxn" => Some(5),
+        "paranoid" => Some(6),
+        "mcts" => Some(7),
+        name => difficulty::LEVELS
+            .iter()
+            .position(|level| level.name == name)
+            .map(|i| difficulty::FIRST_LEVEL_TYPE + i as i8),
+    }
+}
+
+// The other way round, for game records
+pub fn player_type_name(player_type: i8) -> &'static str {
+    match player_type {
+        0 => "human",
+        1 => "random",
+        2 => "randosmart",
+        3 => "minimax",
+        4 => "alphabeta",
+        5 => "maxn",
+        6 => "paranoid",
+        7 => "mcts",
+        _ => match difficulty::level(player_type) {
+            Some(level) => level.name,
+            None => "unknown",
+        },
+    }
+}
+
+// Picks a move in any rule set. Humans, random and mcts work the same
+// everywhere. The other bots play on the board when there is one, and
+// otherwise randosmart plays the variant's own quick bot and everything
+// smarter plays the generic alpha-beta, which the difficulty levels cap at
+// their own depth.
+pub fn get_rules_move(player: &Player, rules: &dyn Rules) -> Move {
+    match (player.player_type, rules.board()) {
+        (0, _) => return get_human_move(rules),
+        (1, _) => return engines::random_move(rules),
+        (7, _) => return engines::mcts_move(rules, MCTS_ITERATIONS),
+        (_, Some(board)) => {
+            // The bots try moves out on this copy, which mustn't print
+            let mut copy = board::clone_board(board);
+            board::set_print(&mut copy, false);
+            return get_move(player, &mut copy);
+        }
+        (2, None) => match rules.clone_rules().simple_move() {
+            Some(player_move) => return player_move,
+            None => return engines::random_move(rules),
+        },
+        (player_type, None) => {
+            let depth = generic_depth(difficulty::level(player_type));
+            return engines::alphabeta_move(rules, depth);
+        }
+    }
+}
+
+// Random playouts mcts runs per move
+const MCTS_ITERATIONS: usize = 5000;
+// The generic alpha-beta doesn't order moves or remember positions, so it
+// can't go as deep as search.rs
+const GENERIC_DEPTH: i32 = 5;
+// Most positions the generic alpha-beta may reach on a gravity-free board,
+// where every empty cell is a move, so gomoku gets a shallower search
+const PLACEMENT_POSITIONS: usize = 1_000_000;
+
+// How deep the generic alpha-beta goes for a bot, the levels never go past
+// their own depth
+fn generic_depth(level: Option<&difficulty::Level>) -> i32 {
+    return level.map_or(GENERIC_DEPTH, |level| level.depth.min(GENERIC_DEPTH));
+}
+
+// depth, cut down until the moves to that depth fit in PLACEMENT_POSITIONS
+fn placement_depth(board: &Board, depth: i32) -> i32 {
+    let moves = board::legal_moves(board).len().max(2);
+    let mut placement_depth = 1;
+    while placement_depth < depth
+        && moves
+            .checked_pow(placement_depth as u32 + 1)
+            .is_some_and(|n| n <= PLACEMENT_POSITIONS)
+    {
+        placement_depth += 1;
+    }
+    return placement_depth;
+}
+
+// The smarter bots only know how to drop pieces, so in PopOut they only pop
+// once the board is full and there's nothing else left. Random players and
+// humans can do both. Without gravity there is nothing to drop, so
+// randosmart plays placement_move and minimax, alphabeta and the difficulty
+// levels play the generic alpha-beta. With gravity minimax, alphabeta and
+// the difficulty levels only know about two players, so with more they play
+// paranoid instead.
+pub fn get_move(player: &Player, board: &mut Board) -> Move {
+    let level = difficulty::level(player.player_type);
+    if !board::has_gravity(board) && player.player_type == 2 {
+        return placement_move(player, board);
+    }
+    if !board::has_gravity(board) && ((3..=4).contains(&player.player_type) || level.is_some()) {
+        let depth = placement_depth(board, generic_depth(level));
+        return engines::alphabeta_move(&rules::connect(board::clone_board(board)), depth);
+    }
+    if board::players(board) > 2 && ((3..=4).contains(&player.player_type) || level.is_some()) {
+        return multisearch::paranoid_move(board, PARANOID_DEPTH);
+    }
+    if player.player_type >= 2
+        && board::get_empty_columns(board).is_empty()
+        && !board::legal_moves(board).is_empty()
+    {
+        let depth = generic_depth(level);
+        return engines::alphabeta_move(&rules::connect(board::clone_board(board)), depth);
+    }
+    if let Some(level) = level {
+        return Move::Drop(difficulty::level_move(level, board));
+    }
     match player.player_type {
-        0 => get_human_move(),
-        1 => random_move(board),
-        2 => randosmart_move(&player, board),
-        3 => minimax_move(&player, board),
-        _ => 0,
+        0 => get_human_move(&rules::connect(board::clone_board(board))),
+        1 => random_legal_move(board),
+        2 => Move::Drop(randosmart_move(player, board)),
+        3 => Move::Drop(minimax_move(player, board)),
+        4 => Move::Drop(alphabeta_move(board)),
+        5 => multisearch::maxn_move(board, MAXN_DEPTH),
+        6 => multisearch::paranoid_move(board, PARANOID_DEPTH),
+        7 => engines::mcts_move(&rules::connect(board::clone_board(board)), MCTS_ITERATIONS),
+        _ => random_legal_move(board),
+    }
+}
+
+// Max-n can't prune, so it can't look as far ahead as paranoid
+const MAXN_DEPTH: i32 = 4;
+const PARANOID_DEPTH: i32 = 6;
+
+// Depth the alphabeta player searches to
+const ALPHABETA_DEPTH: i32 = 7;
+
+// Uses the search module, which works on any board size and win length
+pub fn alphabeta_move(board: &mut Board) -> usize {
+    if let Some(result) = book::probe_global(board) {
+        return result.best_move;
     }
+    return search::search(board, ALPHABETA_DEPTH).best_move;
 }
 
 pub fn minimax_move(player: &Player, board: &mut Board) -> usize {
+    // Play straight from the opening book when we can
+    if let Some(result) = book::probe_global(board) {
+        return result.best_move;
+    }
+    // Same for positions that have already been solved
+    if let Some(entry) = database::probe_global(board) {
+        return entry.best_move;
+    }
+
     let mut rng = thread_rng();
     let mut next_turn_wins = Vec::new();
     let mut t_board = board::clone_board(board);
+    board::set_print(&mut t_board, false);
     let depth = 3;
 
-    // finding any immediate wins
-    for i in 0..7 {
+    // finding any immediate wins (under misère rules these are losses, which
+    // the minimax values below already steer clear of)
+    for i in (0..board::width(board)).filter(|_| !board::is_misere(board)) {
         if board::add_piece(&mut t_board, i, player.player_piece) {
             if board::game_over_check(&mut t_board) {
                 next_turn_wins.push(i);
@@ -44,8 +207,7 @@ pub fn minimax_move(player: &Player, board: &mut Board) -> usize {
         }
     }
     // randomly select a move that wins next turn
-    if next_turn_wins.len() > 0 {
-        println!("Found i-winning move: {:?}", next_turn_wins);
+    if !next_turn_wins.is_empty() {
         return *next_turn_wins.choose(&mut rng).unwrap();
     }
 
@@ -56,28 +218,28 @@ pub fn minimax_move(player: &Player, board: &mut Board) -> usize {
     let mut column_values: Vec<i32> = Vec::new();
     for column in empty {
         if board::add_piece(&mut t_board, column, player.player_piece) {
-            column_values.push(minimax(&mut t_board, depth, *player, opponent_color));
+            if board::is_misere(board) && board::game_over_check(&mut t_board) {
+                // Completing a line loses straight away
+                column_values.push(-100 * (depth + 1));
+            } else {
+                column_values.push(minimax(&mut t_board, depth, *player, opponent_color));
+            }
             board::undo_move(&mut t_board);
         } else {
             column_values.push(-f64::INFINITY as i32);
         }
     }
 
-    println!("VALUES: {:?}", column_values);
-
     let empty = board::get_empty_columns(board);
-    println!("COLUMN: {:?}", empty);
     // Return max of the column_values
     let mut max = column_values[0];
     let mut max_index = 0;
-    for i in 0..empty.len() {
-        if column_values[i] > max {
-            max = column_values[i];
+    for (i, value) in column_values.iter().enumerate() {
+        if *value > max {
+            max = *value;
             max_index = i;
         }
     }
-    println!("Max value: {}", max);
-
     return empty[max_index];
 }
 
@@ -92,7 +254,7 @@ pub fn minimax(board: &mut Board, depth: i32, player: Player, color: usize) -> i
 
     // If no more empty columns, return 0, it's a tie
     let empty = board::get_empty_columns(board);
-    if empty.len() == 0 {
+    if empty.is_empty() {
         return 0;
     }
 
@@ -111,19 +273,19 @@ pub fn minimax(board: &mut Board, depth: i32, player: Player, color: usize) -> i
     let lose_score: i32 = -100 * depth;
 
     let mut t_board = board::clone_board(board);
+    board::set_print(&mut t_board, false);
     // For each empty column
     for column in empty.iter() {
         // If we can add a piece to the column
-        let piece_place = board::add_piece(&mut t_board, *column, color);
-        let win = board::game_over_check(board);
-        // If we win, return win score, depending on player color and depth
+        board::add_piece(&mut t_board, *column, color);
+        let win = board::game_over_check(&mut t_board);
+        // If we win, return win score, depending on player color and depth.
+        // Under misère rules the line loses instead.
         if win {
             board::undo_move(&mut t_board);
-            if color == p_color {
-                println!("WON {} {}", win_score, column);
+            if (color == p_color) != board::is_misere(board) {
                 return win_score;
             } else {
-                println!("LOST {} {}", lose_score, column);
                 return lose_score;
             }
         }
@@ -145,29 +307,38 @@ pub fn minimax(board: &mut Board, depth: i32, player: Player, color: usize) -> i
 //  Looks for an immediate win, if it can't find one, it looks for an immediate loss,
 // if it can't find one, it makes a random move
 pub fn randosmart_move(player: &Player, board: &mut Board) -> usize {
+    // Under misère rules lines lose, so there is nothing to win or block,
+    // just columns to stay out of
+    if board::is_misere(board) {
+        return misere_safe_move(player, board);
+    }
     // Checks if randomsmart can win by placing a piece in some column
-    for i in 0..6 {
-        board::add_piece(board, i, player.player_piece);
-        if board::game_over_check(board) {
-            println!("Found a winning move: {}", i);
-            return i;
+    for i in 0..board::width(board) {
+        if board::add_piece(board, i, player.player_piece) {
+            if board::game_over_check(board) {
+                return i;
+            }
+            board::undo_move(board);
         }
-        board::undo_move(board);
     }
 
     // Clone board
     let mut temp_board = board::clone_board(board);
-    for i in 0..6 {
-        // Get opponent's piece and place it in column i
-        let opponent_piece = if player.player_piece == 1 { 2 } else { 1 };
-        board::add_piece(&mut temp_board, i, opponent_piece);
-
-        // If opponent can win, place piece in column i to block win
-        if board::game_over_check(&mut temp_board) {
-            return i;
-        } else {
-            // Undo the move
-            board::undo_move(&mut temp_board);
+    // With more than two players, block whoever plays next first
+    for opponent_piece in board::other_pieces(board, player.player_piece) {
+        for i in 0..board::width(board) {
+            // Place opponent's piece in column i
+            if !board::add_piece(&mut temp_board, i, opponent_piece) {
+                continue;
+            }
+
+            // If opponent can win, place piece in column i to block win
+            if board::game_over_check(&mut temp_board) {
+                return i;
+            } else {
+                // Undo the move
+                board::undo_move(&mut temp_board);
+            }
         }
     }
 
@@ -175,6 +346,23 @@ pub fn randosmart_move(player: &Player, board: &mut Board) -> usize {
     return random_move(board);
 }
 
+// A random column that doesn't complete a line, if there is one
+fn misere_safe_move(player: &Player, board: &mut Board) -> usize {
+    let mut safe = Vec::new();
+    for column in board::get_empty_columns(board) {
+        board::add_piece(board, column, player.player_piece);
+        if !board::game_over_check(board) {
+            safe.push(column);
+        }
+        board::undo_move(board);
+    }
+    let mut rng = thread_rng();
+    match safe.choose(&mut rng) {
+        Some(column) => return *column,
+        None => return random_move(board),
+    }
+}
+
 pub fn random_move(board: &mut Board) -> usize {
     let lowest = board.lowest_empty.clone();
     // Remove the columns that are full with their index
@@ -192,34 +380,156 @@ pub fn random_move(board: &mut Board) -> usize {
 
     let mut rng = thread_rng();
     let num = empty.choose(&mut rng);
-    return *num.unwrap() as usize;
+    return *num.unwrap();
+}
+
+// randosmart for gravity-free boards: takes a win, else blocks one, else
+// plays a random empty cell
+pub fn placement_move(player: &Player, board: &mut Board) -> Move {
+    let moves = board::legal_moves(board);
+    let mut pieces = vec![player.player_piece];
+    pieces.extend(board::other_pieces(board, player.player_piece));
+    for piece in pieces {
+        for cell in moves.iter() {
+            board::make_move(board, *cell, piece);
+            let win = board::game_over_check(board);
+            board::undo_move(board);
+            if win {
+                return *cell;
+            }
+        }
+    }
+    let mut rng = thread_rng();
+    return *moves.choose(&mut rng).unwrap();
+}
+
+// Picks any legal move, including pops in PopOut
+pub fn random_legal_move(board: &mut Board) -> Move {
+    let moves = board::legal_moves(board);
+    let mut rng = thread_rng();
+    return *moves.choose(&mut rng).unwrap();
+}
+
+// What a human typed: a legal move, or one of the words the caller is
+// listening for
+pub enum HumanInput {
+    Move(Move),
+    Command(String),
+}
+
+// Asks until the player types a legal move
+pub fn get_human_move(rules: &dyn Rules) -> Move {
+    loop {
+        if let HumanInput::Move(player_move) = get_human_input(rules, &[]) {
+            return player_move;
+        }
+    }
 }
 
-pub fn get_human_move() -> usize {
-    //println!("Enter a column number (1-7): ");
+// Asks until the player types a legal move or one of commands. Running out
+// of input counts as "quit" if that's one of them.
+pub fn get_human_input(rules: &dyn Rules, commands: &[&str]) -> HumanInput {
     let mut player_move = String::new();
-    let mut player_move_result: usize;
     loop {
         player_move.clear();
-        io::stdin()
+        let read = io::stdin()
             .read_line(&mut player_move)
             .expect("Failed to read line");
-        let _player_move: u32 = match player_move.trim().parse::<usize>() {
-            Ok(num) => {
-                player_move_result = num as usize;
-                if player_move_result > 0 && player_move_result < 8 {
-                    break;
-                } else {
-                    //println!("Invalid move");
-                    continue;
-                }
+        if read == 0 && commands.contains(&"quit") {
+            return HumanInput::Command("quit".to_string());
+        }
+        let typed = player_move.trim().to_lowercase();
+        if commands.contains(&typed.as_str()) {
+            return HumanInput::Command(typed);
+        }
+        // The hint display, for boards that can be analyzed
+        if typed == "hint" {
+            match rules.board() {
+                Some(board) => print!("{}", threats::describe(board, &threats::analyze(board))),
+                None => println!("No hints for this variant"),
             }
-            Err(_) => {
-                //println!("Invalid input");
+            continue;
+        }
+        match rules.parse_move(&player_move) {
+            Some(parsed) if rules.legal_moves().contains(&parsed) => {
+                println!();
+                return HumanInput::Move(parsed);
+            }
+            _ => {
+                //println!("Invalid move");
                 continue;
             }
-        };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Nothing can be dropped on a full PopOut board, only popped
+    fn full_popout_board() -> Board {
+        let mut board = board::new_popout_board(7, 6);
+        board::set_print(&mut board, false);
+        board::setup_from_grid(
+            &mut board,
+            "XOXOXOX/XOXOXOX/OXOXOXO/OXOXOXO/XOXOXOX/XOXOXOX",
+        )
+        .unwrap();
+        return board;
+    }
+
+    #[test]
+    fn every_bot_pops_on_a_full_popout_board() {
+        let board = full_popout_board();
+        assert!(board::get_empty_columns(&board).is_empty());
+        let legal = board::legal_moves(&board);
+        assert!(!legal.is_empty());
+        let last_type = difficulty::FIRST_LEVEL_TYPE + difficulty::LEVELS.len() as i8 - 1;
+        for player_type in 1..=last_type {
+            let player = new_player(player_type, board::next_piece(&board));
+            let player_move = get_move(&player, &mut board::clone_board(&board));
+            assert!(
+                legal.contains(&player_move),
+                "{} played {:?}",
+                player_type_name(player_type),
+                player_move
+            );
+        }
+    }
+
+    fn tictactoe(rows: &str) -> Board {
+        let mut board = board::new_mnk_board(3, 3, 3);
+        board::set_print(&mut board, false);
+        board::setup_from_grid(&mut board, rows).unwrap();
+        return board;
+    }
+
+    #[test]
+    fn searching_bots_search_without_gravity() {
+        let board = tictactoe("XX./OO./...");
+        let last_type = difficulty::FIRST_LEVEL_TYPE + difficulty::LEVELS.len() as i8 - 1;
+        for player_type in (3..=4).chain(difficulty::FIRST_LEVEL_TYPE..=last_type) {
+            let player = new_player(player_type, board::next_piece(&board));
+            let player_move = get_move(&player, &mut board::clone_board(&board));
+            assert_eq!(
+                player_move,
+                Move::Place(0, 2),
+                "{}",
+                player_type_name(player_type)
+            );
+        }
+        // Only the bottom left corner makes two threats at once
+        let board = tictactoe("XOX/.../..O");
+        for player_type in 3..=4 {
+            let player = new_player(player_type, board::next_piece(&board));
+            let player_move = get_move(&player, &mut board::clone_board(&board));
+            assert_eq!(
+                player_move,
+                Move::Place(2, 0),
+                "{}",
+                player_type_name(player_type)
+            );
+        }
     }
-    println!();
-    return player_move_result - 1;
 }
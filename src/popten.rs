@@ -0,0 +1,344 @@
+// Pop Ten. The game is played on a normal board but in two phases:
+//
+// Setup: players take turns dropping pieces, filling the board one row at
+// a time from the bottom, until it is full.
+//
+// Popping: players take turns popping one of their own pieces out of the
+// bottom row. A piece that was part of four in a row when it was popped is
+// kept, and the same player goes again. Any other piece has to be dropped
+// back in at the top of a different column, then it's the other player's
+// turn. The first player to keep TARGET pieces wins.
+//
+// It is played through the Rules trait like every other variant, so the
+// normal game loop, game records and the generic engines all handle it.
+use crate::board;
+use crate::board::{Board, Move};
+use crate::rules::Rules;
+use rand::{seq::SliceRandom, thread_rng};
+
+pub const TARGET: usize = 10;
+// Two players who never line anything up could pop forever
+const MAX_PLIES: usize = 2000;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Phase {
+    Setup,
+    Pop,
+    // The player who just popped has to put the piece back
+    Return,
+}
+
+pub struct PopTen {
+    pub board: Board,
+    pub phase: Phase,
+    // 1 or 2, like player pieces everywhere else
+    pub to_move: usize,
+    // Pieces each player has kept, kept[0] is player 1's
+    pub kept: [usize; 2],
+    // Column of the last pop, the piece can't go back in there
+    popped_column: usize,
+    plies: usize,
+    // Everything but the board from before each move, so moves can be
+    // undone (the board undoes its own moves)
+    undo_stack: Vec<(Phase, usize, [usize; 2], usize)>,
+}
+
+pub fn new_game(width: usize, height: usize) -> PopTen {
+    PopTen {
+        board: board::new_popout_board(width, height),
+        phase: Phase::Setup,
+        to_move: 1,
+        kept: [0, 0],
+        popped_column: 0,
+        plies: 0,
+        undo_stack: Vec::new(),
+    }
+}
+
+fn clone_game(game: &PopTen) -> PopTen {
+    PopTen {
+        board: board::clone_board(&game.board),
+        phase: game.phase,
+        to_move: game.to_move,
+        kept: game.kept,
+        popped_column: game.popped_column,
+        plies: game.plies,
+        undo_stack: game.undo_stack.clone(),
+    }
+}
+
+fn other(piece: usize) -> usize {
+    if piece == 1 {
+        return 2;
+    }
+    return 1;
+}
+
+pub fn legal_moves(game: &PopTen) -> Vec<Move> {
+    let lowest = &game.board.lowest_empty;
+    match game.phase {
+        Phase::Setup => {
+            // Only columns whose next empty cell is on the row being filled
+            let fill = *lowest.iter().max().unwrap();
+            return (0..lowest.len())
+                .filter(|c| lowest[*c] == fill && fill > 0)
+                .map(Move::Drop)
+                .collect();
+        }
+        Phase::Pop => {
+            let grid = board::get_grid(&game.board);
+            let bottom = grid.len() - 1;
+            return (0..lowest.len())
+                .filter(|c| grid[bottom][*c] == game.to_move)
+                .map(Move::Pop)
+                .collect();
+        }
+        Phase::Return => {
+            let others: Vec<Move> = (0..lowest.len())
+                .filter(|c| *c != game.popped_column && lowest[*c] > 0)
+                .map(Move::Drop)
+                .collect();
+            // If every other column is full it has to go back where it was
+            if others.is_empty() {
+                return vec![Move::Drop(game.popped_column)];
+            }
+            return others;
+        }
+    }
+}
+
+// Plays a move for the player to move, returns false if it isn't legal
+pub fn play(game: &mut PopTen, player_move: Move) -> bool {
+    if !legal_moves(game).contains(&player_move) {
+        return false;
+    }
+    game.undo_stack
+        .push((game.phase, game.to_move, game.kept, game.popped_column));
+    game.plies += 1;
+    match (game.phase, player_move) {
+        (Phase::Setup, Move::Drop(col)) => {
+            board::add_piece(&mut game.board, col, game.to_move);
+            if board::empty_cells(&game.board) == 0 {
+                game.phase = Phase::Pop;
+            }
+            game.to_move = other(game.to_move);
+        }
+        (Phase::Pop, Move::Pop(col)) => {
+            // Whether the piece is kept depends on the board before the pop
+            let bottom = board::get_grid(&game.board).len() - 1;
+            let keep = board::in_line(&game.board, bottom, col);
+            board::pop_piece(&mut game.board, col, game.to_move);
+            if keep {
+                game.kept[game.to_move - 1] += 1;
+            } else {
+                game.popped_column = col;
+                game.phase = Phase::Return;
+            }
+        }
+        (Phase::Return, Move::Drop(col)) => {
+            board::add_piece(&mut game.board, col, game.to_move);
+            game.phase = Phase::Pop;
+            game.to_move = other(game.to_move);
+        }
+        _ => return false,
+    }
+
+    // A player with nothing to pop has to pass
+    if game.phase == Phase::Pop && legal_moves(game).is_empty() {
+        game.to_move = other(game.to_move);
+    }
+    return true;
+}
+
+// Takes back the last move play made
+pub fn undo(game: &mut PopTen) {
+    if let Some((phase, to_move, kept, popped_column)) = game.undo_stack.pop() {
+        board::undo_move(&mut game.board);
+        game.phase = phase;
+        game.to_move = to_move;
+        game.kept = kept;
+        game.popped_column = popped_column;
+        game.plies -= 1;
+    }
+}
+
+// 0 = still playing, 1 or 2 = that player kept TARGET pieces, 3 = draw
+pub fn winner(game: &PopTen) -> usize {
+    for piece in 1..=2 {
+        if game.kept[piece - 1] >= TARGET {
+            return piece;
+        }
+    }
+    if game.plies >= MAX_PLIES || legal_moves(game).is_empty() {
+        return 3;
+    }
+    return 0;
+}
+
+// Keeps a piece whenever it can, otherwise plays randomly
+pub fn bot_move(game: &PopTen) -> Move {
+    let moves = legal_moves(game);
+    if game.phase == Phase::Pop {
+        let bottom = board::get_grid(&game.board).len() - 1;
+        for player_move in moves.iter() {
+            if let Move::Pop(col) = player_move {
+                if board::in_line(&game.board, bottom, *col) {
+                    return *player_move;
+                }
+            }
+        }
+    }
+    let mut rng = thread_rng();
+    return *moves.choose(&mut rng).unwrap();
+}
+
+impl Rules for PopTen {
+    fn players(&self) -> usize {
+        return 2;
+    }
+
+    fn to_move(&self) -> usize {
+        return self.to_move;
+    }
+
+    fn legal_moves(&self) -> Vec<Move> {
+        return legal_moves(self);
+    }
+
+    fn apply(&mut self, player_move: Move) -> bool {
+        return play(self, player_move);
+    }
+
+    fn undo(&mut self) {
+        undo(self);
+    }
+
+    fn result(&mut self) -> usize {
+        return winner(self);
+    }
+
+    // Kept pieces are all that counts in the end
+    fn evaluate(&self, piece: usize) -> i32 {
+        return 100 * (self.kept[piece - 1] as i32 - self.kept[other(piece) - 1] as i32);
+    }
+
+    fn render(&self) -> String {
+        return format!(
+            "{}Phase: {:?}, kept: X {} O {}\n",
+            board::board_to_string(&self.board),
+            self.phase,
+            self.kept[0],
+            self.kept[1]
+        );
+    }
+
+    // People just pick a column, the phase says what happens to it. Game
+    // records write pops as "p4", which works too.
+    fn parse_move(&self, text: &str) -> Option<Move> {
+        let text = text.trim();
+        let column = text.strip_prefix(['p', 'P']).unwrap_or(text).trim();
+        match column.parse::<usize>() {
+            Ok(col) if col > 0 && col <= board::width(&self.board) => match self.phase {
+                Phase::Pop => return Some(Move::Pop(col - 1)),
+                _ => return Some(Move::Drop(col - 1)),
+            },
+            _ => return None,
+        }
+    }
+
+    fn move_to_string(&self, player_move: Move) -> String {
+        match player_move {
+            Move::Pop(col) => return format!("p{}", col + 1),
+            Move::Drop(col) | Move::Place(_, col) => return format!("{}", col + 1),
+        }
+    }
+
+    fn describe_move(&self, player_move: Move) -> String {
+        match player_move {
+            Move::Pop(col) => return format!("popped column {}", col + 1),
+            Move::Drop(col) | Move::Place(_, col) => {
+                return format!("dropped in column {}", col + 1)
+            }
+        }
+    }
+
+    fn clone_rules(&self) -> Box<dyn Rules> {
+        return Box::new(clone_game(self));
+    }
+
+    fn simple_move(&mut self) -> Option<Move> {
+        return Some(bot_move(self));
+    }
+
+    fn input_help(&self) -> Option<String> {
+        return Some("Type a column: to drop into while setting up or putting a piece back, to pop from otherwise".to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Plays the setup phase through, filling each row from the left. On a
+    // 4x4 board that stacks every column with one player's pieces.
+    fn popping_game() -> PopTen {
+        let mut game = new_game(4, 4);
+        board::set_print(&mut game.board, false);
+        while game.phase == Phase::Setup {
+            let first = legal_moves(&game)[0];
+            assert!(play(&mut game, first));
+        }
+        return game;
+    }
+
+    #[test]
+    fn setup_fills_a_row_before_the_next() {
+        let mut game = new_game(4, 4);
+        assert!(play(&mut game, Move::Drop(1)));
+        assert_eq!(
+            legal_moves(&game),
+            vec![Move::Drop(0), Move::Drop(2), Move::Drop(3)]
+        );
+        assert!(!play(&mut game, Move::Drop(1)));
+        let game = popping_game();
+        assert_eq!(game.phase, Phase::Pop);
+        assert_eq!(game.to_move, 1);
+        assert_eq!(legal_moves(&game), vec![Move::Pop(0), Move::Pop(2)]);
+    }
+
+    #[test]
+    fn kept_pieces_go_again_and_others_go_back_in() {
+        let mut game = popping_game();
+        // Both of player 1's columns are four in a row
+        assert!(play(&mut game, Move::Pop(0)));
+        assert!(play(&mut game, Move::Pop(2)));
+        assert_eq!(game.kept, [2, 0]);
+        assert_eq!((game.phase, game.to_move), (Phase::Pop, 1));
+        // Three left in the first column isn't a line, so the piece goes back in
+        assert!(play(&mut game, Move::Pop(0)));
+        assert_eq!(game.kept, [2, 0]);
+        assert_eq!((game.phase, game.to_move), (Phase::Return, 1));
+        assert_eq!(legal_moves(&game), vec![Move::Drop(2)]);
+        assert!(play(&mut game, Move::Drop(2)));
+        assert_eq!((game.phase, game.to_move), (Phase::Pop, 2));
+        assert_eq!(winner(&game), 0);
+    }
+
+    #[test]
+    fn undo_restores_kept_pieces_and_the_phase() {
+        let mut game = popping_game();
+        let cells = board::get_grid(&game.board).clone();
+        for player_move in [Move::Pop(0), Move::Pop(2), Move::Pop(0), Move::Drop(2)] {
+            assert!(play(&mut game, player_move));
+        }
+        for _ in 0..4 {
+            undo(&mut game);
+        }
+        assert_eq!(game.kept, [0, 0]);
+        assert_eq!((game.phase, game.to_move), (Phase::Pop, 1));
+        assert_eq!(board::get_grid(&game.board), &cells);
+        undo(&mut game);
+        assert_eq!((game.phase, game.to_move), (Phase::Setup, 2));
+        assert_eq!(legal_moves(&game), vec![Move::Drop(3)]);
+    }
+}
@@ -0,0 +1,229 @@
+// "Win in N" tactics puzzles, made by playing games and stopping at the
+// positions where the side to move has exactly one move that wins in N
+// moves and nothing wins faster. Every puzzle comes with its solution tree,
+// checked by the search the whole way down.
+//
+// Puzzles are written one per line as JSON, so a file can be appended to
+// and read back a line at a time. Columns are 1-based, like everywhere
+// people see them.
+use crate::board;
+use crate::board::Board;
+use crate::search;
+use crate::search::WIN_SCORE;
+use rand::{seq::SliceRandom, thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+
+// The games the puzzles come from: this often a random move, otherwise the
+// search at GAME_DEPTH, so they look like games people play
+const RANDOM_MOVE_CHANCE: f64 = 0.4;
+const GAME_DEPTH: i32 = 4;
+// Positions this early are openings, not tactics
+const MIN_PLY: usize = 6;
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Puzzle {
+    // The position, as the 1-based columns played to reach it
+    pub moves: String,
+    // Piece of the side to move, who has the win
+    pub to_move: usize,
+    // Moves (of the side to move) it takes to win
+    pub win_in: usize,
+    pub solution: Attack,
+}
+
+// The side with the win to move: every move that wins as fast as possible
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Attack {
+    pub moves: Vec<AttackMove>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AttackMove {
+    pub column: usize,
+    // The other side's best defenses, the ones that last the longest.
+    // Empty when the move wins on the spot.
+    pub defenses: Vec<Defense>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Defense {
+    pub column: usize,
+    pub then: Attack,
+}
+
+// Plays games until count puzzles that win in min_n to max_n moves have
+// turned up, leaving out positions already in seen (canonical keys, so
+// mirror images count as the same puzzle)
+pub fn generate(
+    count: usize,
+    min_n: usize,
+    max_n: usize,
+    width: usize,
+    height: usize,
+    seen: &mut HashSet<u64>,
+) -> Vec<Puzzle> {
+    let mut rng = thread_rng();
+    let mut puzzles = Vec::new();
+    while puzzles.len() < count {
+        let mut position = board::new_board(width, height);
+        board::set_print(&mut position, false);
+        let mut moves: Vec<usize> = Vec::new();
+        loop {
+            if moves.len() >= MIN_PLY {
+                if let Some(puzzle) = find_puzzle(&position, &moves, min_n, max_n, seen) {
+                    println!(
+                        "Puzzle {}: win in {} from {}",
+                        puzzles.len() + 1,
+                        puzzle.win_in,
+                        puzzle.moves
+                    );
+                    puzzles.push(puzzle);
+                    // The next few positions of the same game would make
+                    // much the same puzzle, start a new one
+                    break;
+                }
+            }
+            let columns = board::get_empty_columns(&position);
+            if columns.is_empty() {
+                break;
+            }
+            let column = if rng.gen_bool(RANDOM_MOVE_CHANCE) {
+                *columns.choose(&mut rng).unwrap()
+            } else {
+                search::search(&position, GAME_DEPTH).best_move
+            };
+            let piece = board::next_piece(&position);
+            board::add_piece(&mut position, column, piece);
+            moves.push(column);
+            if board::game_over_check(&mut position) {
+                break;
+            }
+        }
+    }
+    return puzzles;
+}
+
+fn find_puzzle(
+    position: &Board,
+    moves: &[usize],
+    min_n: usize,
+    max_n: usize,
+    seen: &mut HashSet<u64>,
+) -> Option<Puzzle> {
+    let (key, _) = board::canonical_key(position);
+    if seen.contains(&key) {
+        return None;
+    }
+    // A win in N moves is 2N - 1 plies away, so this depth sees every win
+    // up to max_n and the scores of those are exact
+    let result = search::search(position, 2 * max_n as i32 - 1);
+    let win_in = wins_in(result.score)?;
+    if win_in < min_n {
+        return None;
+    }
+    let winning = result
+        .column_values
+        .iter()
+        .filter(|v| v.and_then(wins_in).is_some())
+        .count();
+    if winning != 1 {
+        return None;
+    }
+    seen.insert(key);
+    let mut t_board = board::clone_board(position);
+    return Some(Puzzle {
+        moves: moves.iter().map(|c| (c + 1).to_string()).collect(),
+        to_move: board::next_piece(position),
+        win_in,
+        solution: attack(&mut t_board, win_in),
+    });
+}
+
+// Moves the side to move needs for a win score, None if it isn't one
+fn wins_in(score: i32) -> Option<usize> {
+    if !search::is_win_score(score) || score < 0 {
+        return None;
+    }
+    return Some(((WIN_SCORE - score + 1) / 2) as usize);
+}
+
+// The winning moves from a position won in at most n moves
+fn attack(board: &mut Board, n: usize) -> Attack {
+    let result = search::search(board, 2 * n as i32 - 1);
+    let piece = board::next_piece(board);
+    let mut attack = Attack { moves: Vec::new() };
+    let win_in = match wins_in(result.score) {
+        Some(win_in) => win_in,
+        None => return attack,
+    };
+    for (column, value) in result.column_values.iter().enumerate() {
+        if *value != Some(result.score) {
+            continue;
+        }
+        board::add_piece(board, column, piece);
+        let defenses = if board::game_over_check(board) {
+            Vec::new()
+        } else {
+            defend(board, win_in - 1)
+        };
+        board::undo_move(board);
+        attack.moves.push(AttackMove {
+            column: column + 1,
+            defenses,
+        });
+    }
+    return attack;
+}
+
+// The replies that hold out longest against a win in n moves. The scores
+// are the defending side's, so wins_in(-score) is how long each lasts.
+fn defend(board: &mut Board, n: usize) -> Vec<Defense> {
+    let result = search::search(board, 2 * n as i32);
+    let mut defenses = Vec::new();
+    for (column, value) in result.column_values.iter().enumerate() {
+        if *value != Some(result.score) {
+            continue;
+        }
+        let piece = board::next_piece(board);
+        board::add_piece(board, column, piece);
+        let win_in = wins_in(-result.score).unwrap_or(n);
+        defenses.push(Defense {
+            column: column + 1,
+            then: attack(board, win_in),
+        });
+        board::undo_move(board);
+    }
+    return defenses;
+}
+
+pub fn save(puzzles: &[Puzzle], path: &str) -> io::Result<()> {
+    let mut text = String::new();
+    for puzzle in puzzles {
+        text.push_str(&serde_json::to_string(puzzle).unwrap());
+        text.push('\n');
+    }
+    return fs::write(path, text);
+}
+
+pub fn load(path: &str) -> io::Result<Vec<Puzzle>> {
+    let text = fs::read_to_string(path)?;
+    let mut puzzles = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(puzzle) => puzzles.push(puzzle),
+            Err(e) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", i + 1, e),
+                ))
+            }
+        }
+    }
+    return Ok(puzzles);
+}
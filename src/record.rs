@@ -0,0 +1,147 @@
+// Game records: plain text files with everything needed to replay a game
+// in any rule set, one "key value" line each:
+//
+//   variant popout
+//   args                     (the variant's extra arguments, if any)
+//   players human alphabeta
+//   start grid ......./......./......./......./......./...X...
+//   result 1
+//   moves 4 4 p4 3
+//
+// There is a start line per starting position option the game was played
+// with (see start_options in main.rs). Moves are written by the rule set's
+// move_to_string and read back with its parse_move, so records work for
+// any Rules implementation. Lines starting with ';' are comments.
+use std::fs;
+use std::io;
+
+pub struct Record {
+    pub variant: String,
+    pub variant_args: Vec<String>,
+    pub players: Vec<String>,
+    // Starting position options, name (without the dashes) and value
+    pub start: Vec<(String, String)>,
+    // Like the game loop: the winning piece, players + 1 for a tie
+    pub result: usize,
+    pub moves: Vec<String>,
+}
+
+pub fn to_text(record: &Record) -> String {
+    let mut out = String::new();
+    out.push_str(&format!("variant {}\n", record.variant));
+    if !record.variant_args.is_empty() {
+        out.push_str(&format!("args {}\n", record.variant_args.join(" ")));
+    }
+    out.push_str(&format!("players {}\n", record.players.join(" ")));
+    for (name, value) in record.start.iter() {
+        out.push_str(&format!("start {} {}\n", name, value));
+    }
+    out.push_str(&format!("result {}\n", record.result));
+    out.push_str(&format!("moves {}\n", record.moves.join(" ")));
+    return out;
+}
+
+pub fn parse(text: &str) -> Result<Record, String> {
+    let mut record = Record {
+        variant: String::new(),
+        variant_args: Vec::new(),
+        players: Vec::new(),
+        start: Vec::new(),
+        result: 0,
+        moves: Vec::new(),
+    };
+    for line in text.lines().map(|l| l.trim()) {
+        if line.is_empty() || line.starts_with(';') {
+            continue;
+        }
+        let (key, value) = line.split_once(' ').unwrap_or((line, ""));
+        let words = || value.split_whitespace().map(|w| w.to_string()).collect();
+        match key {
+            "variant" => record.variant = value.trim().to_string(),
+            "args" => record.variant_args = words(),
+            "players" => record.players = words(),
+            "start" => match value.trim().split_once(' ') {
+                Some((name, value)) => record
+                    .start
+                    .push((name.to_string(), value.trim().to_string())),
+                None => return Err(format!("start line without a value: {}", line)),
+            },
+            "result" => match value.trim().parse() {
+                Ok(result) => record.result = result,
+                Err(_) => return Err(format!("invalid result: {}", value)),
+            },
+            "moves" => record.moves = words(),
+            _ => return Err(format!("unknown line: {}", line)),
+        }
+    }
+    if record.variant.is_empty() {
+        return Err("record has no variant".to_string());
+    }
+    return Ok(record);
+}
+
+pub fn save(record: &Record, path: &str) -> io::Result<()> {
+    return fs::write(path, to_text(record));
+}
+
+pub fn load(path: &str) -> io::Result<Record> {
+    let text = fs::read_to_string(path)?;
+    return parse(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_parse_every_line() {
+        let text = "; a PopOut game\n\
+                    variant popout\n\
+                    players human alphabeta\n\
+                    start grid ......./......./......./......./......./...X...\n\
+                    start to-move 2\n\
+                    \n\
+                    result 1\n\
+                    moves 4 4 p4 3\n";
+        let record = parse(text).unwrap();
+        assert_eq!(record.variant, "popout");
+        assert!(record.variant_args.is_empty());
+        assert_eq!(record.players, ["human", "alphabeta"]);
+        assert_eq!(
+            record.start,
+            [
+                (
+                    "grid".to_string(),
+                    "......./......./......./......./......./...X...".to_string()
+                ),
+                ("to-move".to_string(), "2".to_string()),
+            ]
+        );
+        assert_eq!(record.result, 1);
+        assert_eq!(record.moves, ["4", "4", "p4", "3"]);
+    }
+
+    #[test]
+    fn records_survive_a_round_trip() {
+        let record = Record {
+            variant: "mnk".to_string(),
+            variant_args: vec!["4".to_string(), "4".to_string(), "3".to_string()],
+            players: vec!["random".to_string(), "mcts".to_string()],
+            start: vec![("start".to_string(), "1:1 2:2".to_string())],
+            result: 3,
+            moves: vec!["1:2".to_string(), "3:3".to_string()],
+        };
+        let parsed = parse(&to_text(&record)).unwrap();
+        assert_eq!(to_text(&parsed), to_text(&record));
+        assert_eq!(parsed.variant_args, record.variant_args);
+        assert_eq!(parsed.start, record.start);
+    }
+
+    #[test]
+    fn broken_records_are_rejected() {
+        assert!(parse("players human human\nmoves 4").is_err());
+        assert!(parse("variant standard\nresult red").is_err());
+        assert!(parse("variant standard\nstart grid").is_err());
+        assert!(parse("variant standard\nwinner 1").is_err());
+    }
+}
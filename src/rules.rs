@@ -0,0 +1,265 @@
+// Rule sets as plug-ins. The game loop, play and tournament, game records
+// and the generic engines in engines.rs only talk to the Rules trait, so a
+// new variant plugs in by implementing it.
+//
+// ConnectRules is the default: Connect 4 on a Board, along with every
+// variant that is just a differently set up Board (PopOut, cylinder, misère,
+// m,n,k-games, obstacles, more players). Pop Ten keeps its own state and
+// implements Rules itself, see popten.rs.
+use crate::board;
+use crate::board::{Board, Move};
+use crate::multisearch;
+use crate::search;
+
+pub trait Rules {
+    fn players(&self) -> usize;
+    // Piece of the player to move, 1 to players()
+    fn to_move(&self) -> usize;
+    fn legal_moves(&self) -> Vec<Move>;
+    // Plays a move for the player to move. Returns false and changes
+    // nothing if the move isn't legal.
+    fn apply(&mut self, player_move: Move) -> bool;
+    // Takes back the last move apply played
+    fn undo(&mut self);
+    // 0 = still playing, otherwise the winning piece, or players() + 1
+    // for a tie
+    fn result(&mut self) -> usize;
+    // How good the position looks for piece, higher is better. Only used
+    // on positions where the game isn't over.
+    fn evaluate(&self, piece: usize) -> i32;
+    fn render(&self) -> String;
+    // Reads a move the way people type them and move_to_string writes them
+    fn parse_move(&self, text: &str) -> Option<Move>;
+    fn move_to_string(&self, player_move: Move) -> String;
+    // What happened, for "Player 1 <description>"
+    fn describe_move(&self, player_move: Move) -> String;
+    fn clone_rules(&self) -> Box<dyn Rules>;
+
+    // The board underneath, for the bots and tools that only know about
+    // boards. Rule sets that aren't played on a plain Board return None.
+    fn board(&self) -> Option<&Board> {
+        return None;
+    }
+    fn board_mut(&mut self) -> Option<&mut Board> {
+        return None;
+    }
+    // The variant's own quick bot, if it has one
+    fn simple_move(&mut self) -> Option<Move> {
+        return None;
+    }
+    // How to type a move, if it's anything more than a column number
+    fn input_help(&self) -> Option<String> {
+        return None;
+    }
+}
+
+pub struct ConnectRules {
+    board: Board,
+}
+
+pub fn connect(board: Board) -> ConnectRules {
+    ConnectRules { board }
+}
+
+impl Rules for ConnectRules {
+    fn players(&self) -> usize {
+        return board::players(&self.board);
+    }
+
+    fn to_move(&self) -> usize {
+        return board::next_piece(&self.board);
+    }
+
+    fn legal_moves(&self) -> Vec<Move> {
+        return board::legal_moves(&self.board);
+    }
+
+    fn apply(&mut self, player_move: Move) -> bool {
+        if !board::legal_moves(&self.board).contains(&player_move) {
+            return false;
+        }
+        let piece = board::next_piece(&self.board);
+        return board::make_move(&mut self.board, player_move, piece);
+    }
+
+    fn undo(&mut self) {
+        board::undo_move(&mut self.board);
+    }
+
+    fn result(&mut self) -> usize {
+        return board_result(&mut self.board);
+    }
+
+    fn evaluate(&self, piece: usize) -> i32 {
+        if board::players(&self.board) == 2 {
+            return search::evaluate(&self.board, piece);
+        }
+        return multisearch::evaluate_all(&self.board)[piece - 1];
+    }
+
+    fn render(&self) -> String {
+        return board::board_to_string(&self.board);
+    }
+
+    // "4" drops in column 4, "p4" pops it in PopOut and "2 3" (or "2:3")
+    // plays row 2, column 3 on gravity-free boards
+    fn parse_move(&self, text: &str) -> Option<Move> {
+        let text = text.trim();
+        let height = board::get_grid(&self.board).len();
+        let width = board::width(&self.board);
+        if !board::has_gravity(&self.board) {
+            let numbers: Vec<usize> = text
+                .split([' ', ':', ','])
+                .filter_map(|n| n.parse().ok())
+                .collect();
+            match numbers[..] {
+                [row, col] if row > 0 && row <= height && col > 0 && col <= width => {
+                    return Some(Move::Place(row - 1, col - 1));
+                }
+                _ => return None,
+            }
+        }
+        let (pop, column) = match text.strip_prefix(['p', 'P']) {
+            Some(rest) if board::is_popout(&self.board) => (true, rest.trim()),
+            _ => (false, text),
+        };
+        match column.parse::<usize>() {
+            Ok(col) if col > 0 && col <= width && pop => return Some(Move::Pop(col - 1)),
+            Ok(col) if col > 0 && col <= width => return Some(Move::Drop(col - 1)),
+            _ => return None,
+        }
+    }
+
+    fn move_to_string(&self, player_move: Move) -> String {
+        match player_move {
+            Move::Drop(col) => return format!("{}", col + 1),
+            Move::Pop(col) => return format!("p{}", col + 1),
+            Move::Place(row, col) => return format!("{}:{}", row + 1, col + 1),
+        }
+    }
+
+    fn describe_move(&self, player_move: Move) -> String {
+        match player_move {
+            Move::Drop(col) => return format!("moved to column {}", col + 1),
+            Move::Pop(col) => return format!("popped column {}", col + 1),
+            Move::Place(row, col) => return format!("played row {}, column {}", row + 1, col + 1),
+        }
+    }
+
+    fn clone_rules(&self) -> Box<dyn Rules> {
+        return Box::new(connect(board::clone_board(&self.board)));
+    }
+
+    fn board(&self) -> Option<&Board> {
+        return Some(&self.board);
+    }
+
+    fn board_mut(&mut self) -> Option<&mut Board> {
+        return Some(&mut self.board);
+    }
+
+    fn input_help(&self) -> Option<String> {
+        if !board::has_gravity(&self.board) {
+            return Some("Type a row and a column (like 2 3) to play a cell".to_string());
+        }
+        if board::is_popout(&self.board) {
+            return Some(
+                "Type a column to drop a piece, or p and a column (like p4) to pop one".to_string(),
+            );
+        }
+        return None;
+    }
+}
+
+//0 is not game over, 1 red won, 2 yellow won, 3 tie
+// With more players any piece can win and a tie is board::tie_result
+pub fn board_result(board: &mut Board) -> usize {
+    if board::is_popout(board) {
+        return popout_result(board);
+    }
+    let mut game_over_status: usize = 0;
+    // game_over_check looks at the last move, set-up positions might not
+    // have one yet
+    let win: bool = board::last_piece(board) != 0 && board::game_over_check(board);
+    if win {
+        // Whoever just moved made the line
+        game_over_status = board::last_piece(board);
+        // Under misère rules that loses, so the other player wins
+        if board::is_misere(board) {
+            game_over_status = board::next_player(board, game_over_status);
+        }
+    } else if board::empty_cells(board) == 0 {
+        // Checked after the win so a win with the last piece still counts
+        game_over_status = board::tie_result(board);
+        //println!("TIE");
+    }
+    return game_over_status;
+}
+
+// PopOut games don't end when the board fills up, and a pop can connect
+// four for either player (or both). If both connect, the player who popped
+// wins. The game is a draw when a position repeats REPETITION_LIMIT times
+// or the player to move has nothing legal to do.
+fn popout_result(board: &mut Board) -> usize {
+    let winners = board::winners(board);
+    let mover = board::last_piece(board);
+    if winners.contains(&mover) {
+        return mover;
+    }
+    if let Some(other) = winners.first() {
+        return *other;
+    }
+    if board::repetition_count(board) >= board::REPETITION_LIMIT {
+        return board::tie_result(board);
+    }
+    if board::legal_moves(board).is_empty() {
+        return board::tie_result(board);
+    }
+    return 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn popout(rows: &str) -> Board {
+        let mut board = board::new_popout_board(7, 6);
+        board::set_print(&mut board, false);
+        board::setup_from_grid(&mut board, rows).unwrap();
+        return board;
+    }
+
+    #[test]
+    fn a_pop_that_connects_for_both_wins_for_the_popper() {
+        // Popping column 1 makes four along the bottom two rows, X's above O's
+        let mut board = popout("......./......./......./X....../OXXX.../XOOO..O");
+        assert!(board::make_move(&mut board, Move::Pop(0), 1));
+        assert_eq!(board::winners(&board).len(), 2);
+        assert_eq!(board_result(&mut board), 1);
+    }
+
+    #[test]
+    fn a_pop_that_connects_for_the_other_player_loses() {
+        let mut board = popout("......./......./......./O....../XOOO.../XXXO...");
+        assert!(board::make_move(&mut board, Move::Pop(0), 1));
+        assert_eq!(board::winners(&board), vec![2]);
+        assert_eq!(board_result(&mut board), 2);
+    }
+
+    #[test]
+    fn the_third_repetition_is_a_draw() {
+        let mut board = board::new_popout_board(7, 6);
+        board::set_print(&mut board, false);
+        let moves = [Move::Drop(0), Move::Drop(1), Move::Pop(0), Move::Pop(1)];
+        for round in 1..board::REPETITION_LIMIT {
+            for (i, player_move) in moves.iter().enumerate() {
+                assert_eq!(board_result(&mut board), 0);
+                assert!(board::make_move(&mut board, *player_move, i % 2 + 1));
+            }
+            assert_eq!(board::repetition_count(&board), round + 1);
+        }
+        assert_eq!(board_result(&mut board), board::tie_result(&board));
+        board::undo_move(&mut board);
+        assert_eq!(board_result(&mut board), 0);
+    }
+}
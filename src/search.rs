@@ -0,0 +1,503 @@
+// Alpha-beta (negamax) search that reports more than just a column: the
+// score, the principal variation and a value for every column, so it can
+// back analysis tools as well as the bots.
+//
+// Scores are always from the point of view of the side to move. A win is
+// WIN_SCORE minus the number of plies it takes, so faster wins score higher.
+// Under misère rules completing a line is a loss instead, see line_score.
+use crate::board;
+use crate::board::Board;
+use crate::database;
+use crate::stats;
+use crate::stats::SearchStats;
+use std::cell::RefCell;
+use std::time::Instant;
+
+pub const WIN_SCORE: i32 = 100000;
+// Scores further from 0 than this are wins or losses, not evaluations
+const WIN_THRESHOLD: i32 = WIN_SCORE - 1000;
+// Wins are packed as +-(PACKED_WIN - plies to win) so they fit in an i16
+const PACKED_WIN: i32 = 32000;
+// Solved positions with fewer empty cells than this are quick to solve
+// again and aren't worth a spot in the database
+const MIN_STORE_EMPTY: i32 = 8;
+// Number of slots in the transposition table, a power of two
+const TT_SIZE: usize = 1 << 20;
+// Close to the leaves looking positions up costs more than searching them
+const TT_MIN_DEPTH: i32 = 3;
+
+pub struct SearchResult {
+    // 0-based column, like everywhere else in the code
+    pub best_move: usize,
+    pub score: i32,
+    // Principal variation, starting with best_move
+    pub pv: Vec<usize>,
+    // Score of playing each column, None if the column is full
+    pub column_values: Vec<Option<i32>>,
+    pub stats: SearchStats,
+}
+
+// Returns true if a score means someone has a forced win
+pub fn is_win_score(score: i32) -> bool {
+    return score.abs() > WIN_THRESHOLD;
+}
+
+// Scores inside the search count plies from the root. Stored scores count
+// from the position itself, so they mean the same thing at any ply.
+pub fn to_relative(score: i32, ply: i32) -> i32 {
+    if score > WIN_THRESHOLD {
+        return score + ply;
+    }
+    if score < -WIN_THRESHOLD {
+        return score - ply;
+    }
+    return score;
+}
+
+pub fn from_relative(score: i32, ply: i32) -> i32 {
+    if score > WIN_THRESHOLD {
+        return score - ply;
+    }
+    if score < -WIN_THRESHOLD {
+        return score + ply;
+    }
+    return score;
+}
+
+// Squeezes a score into an i16 for the files on disk. Evaluations are
+// clamped, wins and losses keep how many plies away they are.
+pub fn pack_score(score: i32) -> i16 {
+    if is_win_score(score) {
+        let plies = WIN_SCORE - score.abs();
+        return (score.signum() * (PACKED_WIN - plies)) as i16;
+    }
+    return score.clamp(-(PACKED_WIN - 1001), PACKED_WIN - 1001) as i16;
+}
+
+pub fn unpack_score(value: i16) -> i32 {
+    let value = value as i32;
+    if value.abs() > PACKED_WIN - 1000 {
+        let plies = PACKED_WIN - value.abs();
+        return value.signum() * (WIN_SCORE - plies);
+    }
+    return value;
+}
+
+// Human readable version of a score, from the side to move's point of view
+pub fn describe_score(score: i32) -> String {
+    if score > WIN_THRESHOLD {
+        return format!("win in {} plies", WIN_SCORE - score);
+    }
+    if score < -WIN_THRESHOLD {
+        return format!("loss in {} plies", WIN_SCORE + score);
+    }
+    return format!("{:+}", score);
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Bound {
+    Exact,
+    // The real score is at least this much (the search failed high)
+    Lower,
+    // The real score is at most this much (the search failed low)
+    Upper,
+}
+
+// Transposition table slot. Like the other stores it is keyed by
+// board::canonical_key, so best_move is in the canonical orientation.
+#[derive(Copy, Clone)]
+struct TtEntry {
+    key: u64,
+    depth: i32,
+    // Counted from the position itself, see to_relative
+    score: i32,
+    bound: Bound,
+    best_move: usize,
+    // Which search stored it, entries left over from earlier searches
+    // don't count
+    generation: u32,
+}
+
+struct Searcher {
+    stats: SearchStats,
+    // Positions already searched in this search, reached again through a
+    // different move order. Newer entries replace older ones in a slot.
+    table: Vec<Option<TtEntry>>,
+    generation: u32,
+}
+
+thread_local! {
+    // Each thread keeps its table between searches, along with the last
+    // generation handed out, so a search doesn't have to allocate or clear
+    // a whole table first
+    static TABLE: RefCell<(u32, Vec<Option<TtEntry>>)> = const { RefCell::new((0, Vec::new())) };
+}
+
+fn new_searcher(depth: i32) -> Searcher {
+    let (generation, mut table) = TABLE.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        cell.0 = cell.0.wrapping_add(1);
+        return (cell.0, std::mem::take(&mut cell.1));
+    });
+    if table.is_empty() {
+        table = vec![None; TT_SIZE];
+    } else if generation == 0 {
+        // Generations wrapped around, old entries could pass as new ones
+        table.fill(None);
+    }
+    Searcher {
+        stats: stats::new_stats(depth),
+        table,
+        generation,
+    }
+}
+
+// Hands the table back to the thread for the next search
+fn release_table(searcher: &mut Searcher) {
+    let table = std::mem::take(&mut searcher.table);
+    TABLE.with(|cell| cell.borrow_mut().1 = table);
+}
+
+// The entry in slot if it's for this position and from this search
+fn probe_table(searcher: &Searcher, slot: usize, key: u64) -> Option<TtEntry> {
+    return searcher.table[slot]
+        .filter(|entry| entry.key == key && entry.generation == searcher.generation);
+}
+
+// Tries the center columns first, they are usually the best moves which
+// makes alpha-beta cut off a lot more
+pub fn ordered_moves(board: &Board) -> Vec<usize> {
+    let mut moves = board::get_empty_columns(board);
+    let center = (board.lowest_empty.len() as isize - 1) / 2;
+    moves.sort_by_key(|c| ((*c as isize - center).abs(), *c));
+    return moves;
+}
+
+// Searches all the way to the end of the game, so every score is exact: a
+// win, a loss or 0 for a draw
+pub fn solve(board: &Board) -> SearchResult {
+    return search(board, board::empty_cells(board) as i32);
+}
+
+pub fn search(board: &Board, depth: i32) -> SearchResult {
+    let mut t_board = board::clone_board(board);
+    board::set_print(&mut t_board, false);
+    let piece = board::next_piece(board);
+    let mut searcher = new_searcher(depth);
+    // Setting up the table isn't part of the search
+    let start = Instant::now();
+
+    let mut column_values: Vec<Option<i32>> = vec![None; board.lowest_empty.len()];
+    let mut best_score = -WIN_SCORE - 1;
+    let mut best_move = 0;
+    let mut pv = Vec::new();
+
+    // Every root move is searched with a full window so its value is exact
+    for column in ordered_moves(board) {
+        let mut line = Vec::new();
+        board::add_piece(&mut t_board, column, piece);
+        let score = if board::game_over_check(&mut t_board) {
+            line_score(&t_board, 0)
+        } else {
+            -negamax(
+                &mut searcher,
+                &mut t_board,
+                depth - 1,
+                -WIN_SCORE - 1,
+                WIN_SCORE + 1,
+                board::next_player(board, piece),
+                1,
+                &mut line,
+            )
+        };
+        board::undo_move(&mut t_board);
+
+        column_values[column] = Some(score);
+        if score > best_score {
+            best_score = score;
+            best_move = column;
+            pv = vec![column];
+            pv.append(&mut line);
+        }
+    }
+    if pv.is_empty() {
+        // Board is full, nothing to play
+        best_score = 0;
+    } else if depth >= board::empty_cells(board) as i32 {
+        // The search went all the way to the end, so the score is exact
+        database::store_global(
+            board,
+            database::Entry {
+                score: best_score,
+                best_move,
+            },
+        );
+    }
+
+    release_table(&mut searcher);
+    stats::finish(&mut searcher.stats, start);
+    return SearchResult {
+        best_move,
+        score: best_score,
+        pv,
+        column_values,
+        stats: searcher.stats,
+    };
+}
+
+#[allow(clippy::too_many_arguments)]
+fn negamax(
+    searcher: &mut Searcher,
+    board: &mut Board,
+    depth: i32,
+    mut alpha: i32,
+    beta: i32,
+    piece: usize,
+    ply: i32,
+    pv: &mut Vec<usize>,
+) -> i32 {
+    stats::node(&mut searcher.stats, ply);
+    pv.clear();
+
+    // Positions solved by an earlier run are already known exactly
+    if let Some(entry) = database::probe_global(board) {
+        pv.push(entry.best_move);
+        return from_relative(entry.score, ply);
+    }
+
+    let use_table = depth >= TT_MIN_DEPTH;
+    let (key, mirrored) = if use_table {
+        board::canonical_key(board)
+    } else {
+        (0, false)
+    };
+    let slot = (key % TT_SIZE as u64) as usize;
+    let mut tt_move = None;
+    let entry = if use_table {
+        let entry = probe_table(searcher, slot, key);
+        stats::tt_probe(&mut searcher.stats, entry.is_some());
+        entry
+    } else {
+        None
+    };
+    if let Some(entry) = entry {
+        let column = if mirrored {
+            board::mirror_column(board, entry.best_move)
+        } else {
+            entry.best_move
+        };
+        tt_move = Some(column);
+        let score = from_relative(entry.score, ply);
+        let usable = match entry.bound {
+            Bound::Exact => true,
+            Bound::Lower => score >= beta,
+            Bound::Upper => score <= alpha,
+        };
+        if entry.depth >= depth && usable {
+            pv.push(column);
+            return score;
+        }
+    }
+
+    let mut moves = ordered_moves(board);
+    // No more empty columns, it's a tie
+    if moves.is_empty() {
+        return 0;
+    }
+    // The best move from last time is the most likely to cut off again
+    if let Some(column) = tt_move {
+        if let Some(i) = moves.iter().position(|c| *c == column) {
+            moves.remove(i);
+            moves.insert(0, column);
+        }
+    }
+
+    // Winning right now beats anything a deeper search could find. Under
+    // misère rules a completed line is a loss, which is handled with the
+    // other moves below.
+    let misere = board::is_misere(board);
+    for column in moves.iter().filter(|_| !misere) {
+        board::add_piece(board, *column, piece);
+        let win = board::game_over_check(board);
+        board::undo_move(board);
+        if win {
+            pv.push(*column);
+            return WIN_SCORE - ply - 1;
+        }
+    }
+
+    if depth <= 0 {
+        return evaluate(board, piece);
+    }
+
+    let original_alpha = alpha;
+    let mut best_score = -WIN_SCORE - 1;
+    let mut line = Vec::new();
+    let mut searched = 0;
+    for (index, column) in moves.into_iter().enumerate() {
+        searched += 1;
+        board::add_piece(board, column, piece);
+        let score = if misere && board::game_over_check(board) {
+            line.clear();
+            line_score(board, ply)
+        } else {
+            -negamax(
+                searcher,
+                board,
+                depth - 1,
+                -beta,
+                -alpha,
+                board::next_player(board, piece),
+                ply + 1,
+                &mut line,
+            )
+        };
+        board::undo_move(board);
+
+        if score > best_score {
+            best_score = score;
+            pv.clear();
+            pv.push(column);
+            pv.extend_from_slice(&line);
+        }
+        if score > alpha {
+            alpha = score;
+        }
+        if alpha >= beta {
+            stats::cutoff(&mut searcher.stats, index);
+            break;
+        }
+    }
+    stats::expanded(&mut searcher.stats, searched);
+
+    // Scores that landed outside the window are only bounds
+    let bound = if best_score <= original_alpha {
+        Bound::Upper
+    } else if best_score >= beta {
+        Bound::Lower
+    } else {
+        Bound::Exact
+    };
+    if use_table {
+        searcher.table[slot] = Some(TtEntry {
+            key,
+            depth,
+            score: to_relative(best_score, ply),
+            bound,
+            best_move: if mirrored {
+                board::mirror_column(board, pv[0])
+            } else {
+                pv[0]
+            },
+            generation: searcher.generation,
+        });
+    }
+
+    // Only exact scores that reached the end of the game go in the database
+    let remaining = board::empty_cells(board) as i32;
+    if depth >= remaining && remaining >= MIN_STORE_EMPTY && bound == Bound::Exact {
+        database::store_global(
+            board,
+            database::Entry {
+                score: to_relative(best_score, ply),
+                best_move: pv[0],
+            },
+        );
+    }
+    return best_score;
+}
+
+// Score for the player who just completed a line with the move at ply
+pub fn line_score(board: &Board, ply: i32) -> i32 {
+    if board::is_misere(board) {
+        return -(WIN_SCORE - ply - 1);
+    }
+    return WIN_SCORE - ply - 1;
+}
+
+// Static evaluation for when the search runs out of depth. Every window of
+// connect-length cells that only one side has pieces in counts for that
+// side, more so the fuller it is, and pieces in the center column get a
+// small bonus (which means little on a cylinder, but doesn't hurt either).
+// Windows with an obstacle in them count for nobody. Under misère rules nearly finished lines are a liability, so it all
+// counts the other way.
+pub fn evaluate(board: &Board, piece: usize) -> i32 {
+    let mut score = 0;
+    for_each_window(board, |cells| {
+        let mut mine = 0;
+        let mut theirs = 0;
+        for cell in cells {
+            if *cell == piece {
+                mine += 1;
+            } else if *cell == 0 {
+                continue;
+            } else if board::is_piece(board, *cell) {
+                theirs += 1;
+            } else {
+                // Nobody can win through an obstacle
+                return;
+            }
+        }
+        score += window_score(mine, theirs, cells.len() as i32);
+    });
+
+    let grid = board::get_grid(board);
+    let center = (grid[0].len() - 1) / 2;
+    for row in grid.iter() {
+        if row[center] == piece {
+            score += 3;
+        } else if board::is_piece(board, row[center]) {
+            score -= 3;
+        }
+    }
+    if board::is_misere(board) {
+        return -score;
+    }
+    return score;
+}
+
+// Calls visit with the cells of every connect-length line on the board
+pub fn for_each_window(board: &Board, mut visit: impl FnMut(&[usize])) {
+    let grid = board::get_grid(board);
+    let mut stack = [0; board::STACK_LINE];
+    let mut heap = Vec::new();
+    let cells = board::line_buffer(&mut stack, &mut heap, board::connect_length(board));
+    board::for_each_line(board, |line| {
+        for (cell, (row, column)) in cells.iter_mut().zip(line) {
+            *cell = grid[*row][*column];
+        }
+        visit(cells);
+    });
+}
+
+// Windows one piece short of a win are worth the most
+pub fn window_score(mine: i32, theirs: i32, connect: i32) -> i32 {
+    if mine > 0 && theirs > 0 {
+        return 0;
+    }
+    let value = |count: i32| {
+        if count == 0 || count >= connect {
+            0
+        } else if count == connect - 1 {
+            50
+        } else if count == connect - 2 {
+            5
+        } else {
+            1
+        }
+    };
+    return value(mine) - value(theirs);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn obstacles_count_for_nobody() {
+        let board =
+            board::from_map("..#....\n.......\n.......\n.......\n.......\n.*.....\n").unwrap();
+        assert_eq!(evaluate(&board, 1), 0);
+        assert_eq!(evaluate(&board, 2), 0);
+    }
+}
@@ -0,0 +1,653 @@
+// Multi-game server: clients connect over plain TCP (one command per line)
+// or WebSocket (one command per text message), sit down at tables, get
+// paired by matchmaking or against a bot, and can watch other tables live.
+//
+// Client -> server commands (case-insensitive):
+//   NAME <name>          set the name shown to other clients
+//   LIST                 list all tables
+//   CREATE [bot]         open a table as X, optionally with a bot as O
+//   JOIN <table>         take the open seat at a table
+//   SEEK                 wait for the next player who also seeks
+//   WATCH <table>        spectate a table
+//   MOVE <column>        drop a piece, columns are 1-7 like the terminal game
+//   LEAVE                leave the current table (forfeits a running game)
+//   HELP / QUIT
+//
+// Server -> client lines start with one of OK, ERROR, TABLE, START, BOARD,
+// MOVED, YOURTURN or OVER, so clients can dispatch on the first word.
+use crate::board;
+use crate::board::Board;
+use crate::player;
+use crate::rules;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use tungstenite::Message;
+
+const WIDTH: usize = 7;
+const HEIGHT: usize = 6;
+// How long a new connection gets to show it's a WebSocket
+const SNIFF_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Copy, Clone, PartialEq)]
+enum Seat {
+    Empty,
+    Client(usize),
+    // Holds the player_type of the bot, see player::new_player
+    Bot(i8),
+}
+
+struct Table {
+    board: Board,
+    turn: i32,
+    // Columns played so far, so spectators joining late can catch up
+    moves: Vec<usize>,
+    // seats[0] plays X (player 1), seats[1] plays O (player 2)
+    seats: [Seat; 2],
+    spectators: Vec<usize>,
+    // 0 = still playing, 1 = X won, 2 = O won, 3 = tie, like rules::board_result
+    winner: usize,
+}
+
+struct Client {
+    name: String,
+    // Lines queued here are written to the client's connection
+    outbox: Sender<String>,
+    table: Option<usize>,
+}
+
+pub struct Lobby {
+    next_client: usize,
+    next_table: usize,
+    clients: HashMap<usize, Client>,
+    tables: BTreeMap<usize, Table>,
+    // Client waiting for an opponent after sending SEEK
+    seeking: Option<usize>,
+    // Tables where a bot is to move, see play_bots
+    bot_turns: Vec<usize>,
+}
+
+pub fn new_lobby() -> Lobby {
+    Lobby {
+        next_client: 1,
+        next_table: 1,
+        clients: HashMap::new(),
+        tables: BTreeMap::new(),
+        seeking: None,
+        bot_turns: Vec::new(),
+    }
+}
+
+pub fn run(addr: &str) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    println!("Server listening on {}", addr);
+    let lobby = Arc::new(Mutex::new(new_lobby()));
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                let lobby = Arc::clone(&lobby);
+                thread::spawn(move || handle_connection(stream, lobby));
+            }
+            Err(e) => println!("Failed to accept connection: {}", e),
+        }
+    }
+    return Ok(());
+}
+
+fn handle_connection(stream: TcpStream, lobby: Arc<Mutex<Lobby>>) {
+    // WebSocket clients open with an HTTP upgrade request, everyone else
+    // is treated as a line based TCP client. Those can sit waiting for the
+    // welcome line before they say anything, so a quiet start means TCP.
+    let mut first = [0; 4];
+    if stream.set_read_timeout(Some(SNIFF_TIMEOUT)).is_err() {
+        return;
+    }
+    let is_websocket = match stream.peek(&mut first) {
+        Ok(n) => n == 4 && &first == b"GET ",
+        Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => false,
+        Err(_) => return,
+    };
+    if stream.set_read_timeout(None).is_err() {
+        return;
+    }
+
+    let (outbox, inbox) = channel();
+    let id = add_client(&mut lobby.lock().unwrap(), outbox);
+    if is_websocket {
+        serve_websocket(stream, &lobby, id, inbox);
+    } else {
+        serve_tcp(stream, &lobby, id, inbox);
+    }
+    remove_client(&mut lobby.lock().unwrap(), id);
+}
+
+fn serve_tcp(stream: TcpStream, lobby: &Arc<Mutex<Lobby>>, id: usize, inbox: Receiver<String>) {
+    // Writes happen on their own thread so other clients' moves show up
+    // while this one is blocked reading
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    thread::spawn(move || {
+        for line in inbox {
+            if writeln!(writer, "{}", line).is_err() {
+                break;
+            }
+        }
+    });
+
+    let reader = BufReader::new(stream);
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if !handle_command(&mut lobby.lock().unwrap(), id, &line) {
+            break;
+        }
+        play_bots(lobby);
+    }
+}
+
+fn serve_websocket(
+    stream: TcpStream,
+    lobby: &Arc<Mutex<Lobby>>,
+    id: usize,
+    inbox: Receiver<String>,
+) {
+    // A WebSocket can't be split into a reader and a writer, so reads time
+    // out regularly to give queued lines a chance to go out
+    if stream
+        .set_read_timeout(Some(Duration::from_millis(50)))
+        .is_err()
+    {
+        return;
+    }
+    let mut socket = match tungstenite::accept(stream) {
+        Ok(socket) => socket,
+        Err(_) => return,
+    };
+
+    loop {
+        match socket.read() {
+            Ok(Message::Text(text)) => {
+                let mut keep_going = true;
+                for line in text.as_str().lines() {
+                    keep_going = handle_command(&mut lobby.lock().unwrap(), id, line);
+                    play_bots(lobby);
+                    if !keep_going {
+                        break;
+                    }
+                }
+                if !keep_going {
+                    let _ = socket.close(None);
+                    break;
+                }
+            }
+            Ok(Message::Close(_)) => break,
+            Ok(_) => {}
+            Err(tungstenite::Error::Io(e))
+                if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => {}
+            Err(_) => break,
+        }
+
+        while let Ok(line) = inbox.try_recv() {
+            if socket.send(Message::text(line)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// Lines for the new client go to outbox, whatever is reading the other
+// end of it passes them on to the connection
+pub fn add_client(lobby: &mut Lobby, outbox: Sender<String>) -> usize {
+    let id = lobby.next_client;
+    lobby.next_client += 1;
+    lobby.clients.insert(
+        id,
+        Client {
+            name: format!("guest{}", id),
+            outbox,
+            table: None,
+        },
+    );
+    send(
+        lobby,
+        id,
+        &format!("OK welcome guest{}, type HELP for commands", id),
+    );
+    return id;
+}
+
+pub fn remove_client(lobby: &mut Lobby, id: usize) {
+    leave_table(lobby, id);
+    lobby.clients.remove(&id);
+}
+
+// Returns false when the client asked to disconnect
+pub fn handle_command(lobby: &mut Lobby, id: usize, line: &str) -> bool {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    if words.is_empty() {
+        return true;
+    }
+
+    match words[0].to_uppercase().as_str() {
+        "NAME" => {
+            if words.len() < 2 {
+                send(lobby, id, "ERROR usage: NAME <name>");
+            } else {
+                let name = words[1..].join("_");
+                lobby.clients.get_mut(&id).unwrap().name = name.clone();
+                send(lobby, id, &format!("OK name {}", name));
+            }
+        }
+        "LIST" => list_tables(lobby, id),
+        "CREATE" => {
+            // An optional bot type fills the second seat straight away
+            let bot = match words.get(1) {
+                None => None,
+                Some(name) => match player::parse_player_type(name) {
+                    // The other engines think for too long, or don't
+                    // know this board well enough, to hold up a table
+                    Some(player_type) if (1..=4).contains(&player_type) => Some(player_type),
+                    _ => {
+                        send(
+                            lobby,
+                            id,
+                            "ERROR bot must be random, randosmart, minimax or alphabeta",
+                        );
+                        return true;
+                    }
+                },
+            };
+            leave_table(lobby, id);
+            let table_id = create_table(lobby, Seat::Client(id));
+            send(lobby, id, &format!("OK table {}", table_id));
+            match bot {
+                Some(player_type) => {
+                    lobby.tables.get_mut(&table_id).unwrap().seats[1] = Seat::Bot(player_type);
+                    start_game(lobby, table_id);
+                }
+                None => send(lobby, id, "OK waiting for an opponent"),
+            }
+        }
+        "JOIN" => match parse_table_id(lobby, &words) {
+            Some(table_id) => join_table(lobby, id, table_id),
+            None => send(lobby, id, "ERROR usage: JOIN <table>"),
+        },
+        "SEEK" => seek(lobby, id),
+        "WATCH" => match parse_table_id(lobby, &words) {
+            Some(table_id) => watch_table(lobby, id, table_id),
+            None => send(lobby, id, "ERROR usage: WATCH <table>"),
+        },
+        "MOVE" => {
+            // Columns come in 1-based like the terminal game
+            match words.get(1).and_then(|w| w.parse::<usize>().ok()) {
+                Some(column) if column > 0 => make_move(lobby, id, column - 1),
+                _ => send(lobby, id, "ERROR usage: MOVE <column>"),
+            }
+        }
+        "LEAVE" => {
+            leave_table(lobby, id);
+            send(lobby, id, "OK left table");
+        }
+        "HELP" => send(
+            lobby,
+            id,
+            "OK commands: NAME <name>, LIST, CREATE [random|randosmart|minimax|alphabeta], \
+             JOIN <table>, SEEK, WATCH <table>, MOVE <column>, LEAVE, QUIT",
+        ),
+        "QUIT" => {
+            send(lobby, id, "OK bye");
+            return false;
+        }
+        _ => send(lobby, id, "ERROR unknown command, type HELP"),
+    }
+    return true;
+}
+
+fn parse_table_id(lobby: &Lobby, words: &[&str]) -> Option<usize> {
+    let table_id = words.get(1)?.parse::<usize>().ok()?;
+    if lobby.tables.contains_key(&table_id) {
+        return Some(table_id);
+    }
+    return None;
+}
+
+fn send(lobby: &Lobby, id: usize, line: &str) {
+    if let Some(client) = lobby.clients.get(&id) {
+        // If the connection is already gone there is nobody left to tell
+        let _ = client.outbox.send(line.to_string());
+    }
+}
+
+// Sends a line to both players and every spectator of a table
+fn broadcast(lobby: &Lobby, table_id: usize, line: &str) {
+    let table = &lobby.tables[&table_id];
+    for seat in table.seats.iter() {
+        if let Seat::Client(id) = seat {
+            send(lobby, *id, line);
+        }
+    }
+    for id in table.spectators.iter() {
+        send(lobby, *id, line);
+    }
+}
+
+fn seat_name(lobby: &Lobby, seat: Seat) -> String {
+    match seat {
+        Seat::Empty => "-".to_string(),
+        Seat::Client(id) => match lobby.clients.get(&id) {
+            Some(client) => client.name.clone(),
+            None => "-".to_string(),
+        },
+        Seat::Bot(1) => "bot:random".to_string(),
+        Seat::Bot(2) => "bot:randosmart".to_string(),
+        Seat::Bot(3) => "bot:minimax".to_string(),
+        Seat::Bot(4) => "bot:alphabeta".to_string(),
+        Seat::Bot(_) => "bot".to_string(),
+    }
+}
+
+fn table_status(table: &Table) -> &'static str {
+    if table.winner != 0 {
+        return "finished";
+    }
+    if table.seats.contains(&Seat::Empty) {
+        return "open";
+    }
+    return "playing";
+}
+
+fn list_tables(lobby: &Lobby, id: usize) {
+    for (table_id, table) in lobby.tables.iter() {
+        send(
+            lobby,
+            id,
+            &format!(
+                "TABLE {} {} {} {} {}",
+                table_id,
+                table_status(table),
+                seat_name(lobby, table.seats[0]),
+                seat_name(lobby, table.seats[1]),
+                table.spectators.len()
+            ),
+        );
+    }
+    send(lobby, id, &format!("OK {} tables", lobby.tables.len()));
+}
+
+fn create_table(lobby: &mut Lobby, first_seat: Seat) -> usize {
+    let table_id = lobby.next_table;
+    lobby.next_table += 1;
+    lobby.tables.insert(
+        table_id,
+        Table {
+            board: board::new_board(WIDTH, HEIGHT),
+            turn: 1,
+            moves: Vec::new(),
+            seats: [first_seat, Seat::Empty],
+            spectators: Vec::new(),
+            winner: 0,
+        },
+    );
+    if let Seat::Client(id) = first_seat {
+        lobby.clients.get_mut(&id).unwrap().table = Some(table_id);
+    }
+    return table_id;
+}
+
+fn join_table(lobby: &mut Lobby, id: usize, table_id: usize) {
+    if lobby.clients[&id].table == Some(table_id) {
+        send(lobby, id, "ERROR already at that table");
+        return;
+    }
+    let table = &lobby.tables[&table_id];
+    if table.winner != 0 || !table.seats.contains(&Seat::Empty) {
+        send(lobby, id, "ERROR table is not open");
+        return;
+    }
+    leave_table(lobby, id);
+    // leave_table may have closed the table if we were spectating it alone
+    let table = match lobby.tables.get_mut(&table_id) {
+        Some(table) => table,
+        None => {
+            send(lobby, id, "ERROR table is not open");
+            return;
+        }
+    };
+    let seat = table.seats.iter().position(|s| *s == Seat::Empty).unwrap();
+    table.seats[seat] = Seat::Client(id);
+    lobby.clients.get_mut(&id).unwrap().table = Some(table_id);
+    send(lobby, id, &format!("OK table {}", table_id));
+    start_game(lobby, table_id);
+}
+
+fn seek(lobby: &mut Lobby, id: usize) {
+    match lobby.seeking {
+        Some(other) if other != id => {
+            // Whoever waited longest gets to move first
+            lobby.seeking = None;
+            leave_table(lobby, id);
+            let table_id = create_table(lobby, Seat::Client(other));
+            lobby.tables.get_mut(&table_id).unwrap().seats[1] = Seat::Client(id);
+            lobby.clients.get_mut(&id).unwrap().table = Some(table_id);
+            send(lobby, other, &format!("OK table {}", table_id));
+            send(lobby, id, &format!("OK table {}", table_id));
+            start_game(lobby, table_id);
+        }
+        _ => {
+            leave_table(lobby, id);
+            lobby.seeking = Some(id);
+            send(lobby, id, "OK seeking an opponent");
+        }
+    }
+}
+
+fn watch_table(lobby: &mut Lobby, id: usize, table_id: usize) {
+    leave_table(lobby, id);
+    lobby.tables.get_mut(&table_id).unwrap().spectators.push(id);
+    lobby.clients.get_mut(&id).unwrap().table = Some(table_id);
+    send(lobby, id, &format!("OK watching table {}", table_id));
+    send_position(lobby, id, table_id);
+}
+
+// Catches a client up on a table: the moves so far and the board itself
+fn send_position(lobby: &Lobby, id: usize, table_id: usize) {
+    let table = &lobby.tables[&table_id];
+    let moves: Vec<String> = table.moves.iter().map(|m| (m + 1).to_string()).collect();
+    send(lobby, id, &format!("BOARD {} {}", table_id, moves.join("")));
+    for line in board::board_to_string(&table.board).lines() {
+        send(lobby, id, &format!("BOARD {} {}", table_id, line));
+    }
+}
+
+fn start_game(lobby: &mut Lobby, table_id: usize) {
+    let seats = lobby.tables[&table_id].seats;
+    println!(
+        "Table {}: {} vs {}",
+        table_id,
+        seat_name(lobby, seats[0]),
+        seat_name(lobby, seats[1])
+    );
+    for (i, seat) in seats.iter().enumerate() {
+        if let Seat::Client(id) = seat {
+            send(lobby, *id, &format!("START {} {}", table_id, i + 1));
+        }
+    }
+    next_turn(lobby, table_id);
+}
+
+// Tells whoever is to move that it's their turn. Bots are only queued up
+// here, play_bots moves for them once the lobby lock can be let go.
+fn next_turn(lobby: &mut Lobby, table_id: usize) {
+    let table = &lobby.tables[&table_id];
+    if table.winner != 0 {
+        return;
+    }
+    let player_piece = board::get_player_piece(table.turn);
+    match table.seats[player_piece - 1] {
+        Seat::Client(id) => send(lobby, id, &format!("YOURTURN {}", table_id)),
+        Seat::Bot(_) => lobby.bot_turns.push(table_id),
+        Seat::Empty => {}
+    }
+}
+
+// Moves for every bot whose turn it is. Bots can think for seconds, so the
+// lobby is only locked to copy the board and to play the move, and the
+// move is thrown away if the table changed in the meantime (the client
+// left, say).
+pub fn play_bots(lobby: &Mutex<Lobby>) {
+    loop {
+        let (table_id, played, player_type, player_piece, mut thinking_board) = {
+            let mut lobby = lobby.lock().unwrap();
+            let table_id = match lobby.bot_turns.pop() {
+                Some(table_id) => table_id,
+                None => return,
+            };
+            let table = match lobby.tables.get(&table_id) {
+                Some(table) => table,
+                None => continue,
+            };
+            let player_piece = board::get_player_piece(table.turn);
+            let player_type = match table.seats[player_piece - 1] {
+                Seat::Bot(player_type) => player_type,
+                _ => continue,
+            };
+            (
+                table_id,
+                table.moves.len(),
+                player_type,
+                player_piece,
+                board::clone_board(&table.board),
+            )
+        };
+
+        let bot = player::new_player(player_type, player_piece);
+        let player_move = player::get_move(&bot, &mut board::clone_board(&thinking_board));
+        let mut column = match player_move {
+            board::Move::Drop(column) => column,
+            board::Move::Pop(_) | board::Move::Place(..) => usize::MAX,
+        };
+        // Never let a confused bot stall the table
+        if !board::get_empty_columns(&thinking_board).contains(&column) {
+            column = player::random_move(&mut thinking_board);
+        }
+
+        let mut lobby = lobby.lock().unwrap();
+        let unchanged = match lobby.tables.get(&table_id) {
+            Some(table) => {
+                table.winner == 0
+                    && table.moves.len() == played
+                    && table.seats[player_piece - 1] == Seat::Bot(player_type)
+            }
+            None => false,
+        };
+        if unchanged {
+            apply_move(&mut lobby, table_id, column);
+            next_turn(&mut lobby, table_id);
+        }
+    }
+}
+
+fn make_move(lobby: &mut Lobby, id: usize, column: usize) {
+    let table_id = match lobby.clients[&id].table {
+        Some(table_id) => table_id,
+        None => {
+            send(lobby, id, "ERROR not at a table");
+            return;
+        }
+    };
+    let table = &lobby.tables[&table_id];
+    if table.winner != 0 || table.seats.contains(&Seat::Empty) {
+        send(lobby, id, "ERROR game is not running");
+        return;
+    }
+    let player_piece = board::get_player_piece(table.turn);
+    if table.seats[player_piece - 1] != Seat::Client(id) {
+        send(lobby, id, "ERROR not your turn");
+        return;
+    }
+    if !board::get_empty_columns(&table.board).contains(&column) {
+        send(lobby, id, "ERROR invalid move");
+        return;
+    }
+    apply_move(lobby, table_id, column);
+    next_turn(lobby, table_id);
+}
+
+// Plays a column that is known to be legal and tells everyone at the table
+fn apply_move(lobby: &mut Lobby, table_id: usize, column: usize) {
+    let table = lobby.tables.get_mut(&table_id).unwrap();
+    let player_piece = board::get_player_piece(table.turn);
+    board::add_piece(&mut table.board, column, player_piece);
+    table.moves.push(column);
+    table.turn += 1;
+    table.winner = rules::board_result(&mut table.board);
+    let winner = table.winner;
+
+    broadcast(
+        lobby,
+        table_id,
+        &format!("MOVED {} {} {}", table_id, player_piece, column + 1),
+    );
+    if winner != 0 {
+        finish_game(lobby, table_id);
+    }
+}
+
+fn finish_game(lobby: &Lobby, table_id: usize) {
+    let table = &lobby.tables[&table_id];
+    broadcast(
+        lobby,
+        table_id,
+        &format!("OVER {} {}", table_id, table.winner),
+    );
+    // One line per finished game so the ladder can be scored from the log
+    println!(
+        "RESULT table={} x={} o={} winner={}",
+        table_id,
+        seat_name(lobby, table.seats[0]),
+        seat_name(lobby, table.seats[1]),
+        table.winner
+    );
+}
+
+// Takes a client out of whatever table they are at. Leaving a running game
+// forfeits it, and tables with no humans left are closed. Also stops seeking,
+// since every command that seats a client goes through here first.
+fn leave_table(lobby: &mut Lobby, id: usize) {
+    if lobby.seeking == Some(id) {
+        lobby.seeking = None;
+    }
+    let table_id = match lobby.clients.get_mut(&id).and_then(|c| c.table.take()) {
+        Some(table_id) => table_id,
+        None => return,
+    };
+    let table = lobby.tables.get_mut(&table_id).unwrap();
+    table.spectators.retain(|s| *s != id);
+
+    if let Some(seat) = table.seats.iter().position(|s| *s == Seat::Client(id)) {
+        let was_running = table.winner == 0 && !table.seats.contains(&Seat::Empty);
+        if was_running {
+            // The other seat wins by forfeit
+            table.winner = 2 - seat;
+            finish_game(lobby, table_id);
+        }
+        let table = lobby.tables.get_mut(&table_id).unwrap();
+        table.seats[seat] = Seat::Empty;
+    }
+
+    let table = &lobby.tables[&table_id];
+    let humans_left =
+        table.seats.iter().any(|s| matches!(s, Seat::Client(_))) || !table.spectators.is_empty();
+    if !humans_left {
+        lobby.tables.remove(&table_id);
+    }
+}
@@ -0,0 +1,133 @@
+// Counters the searches fill in as they go, so their speed and how well
+// they prune can be compared between engines and between versions (see the
+// bench command in main.rs).
+//
+//   nodes            positions visited
+//   tt_probes/hits   transposition table lookups, and how many found the
+//                    position (engines without a table leave them at 0)
+//   cutoffs          beta cutoffs by the index of the move that caused
+//                    them, cutoffs[0] being the first move tried. Good move
+//                    ordering puts nearly all of them there.
+//   expanded         positions whose moves were searched, and children how
+//                    many moves that was in all, so children / expanded is
+//                    the effective branching factor
+//   depth            the depth asked for, max_ply the deepest any line got
+use serde::Serialize;
+use std::time::Instant;
+
+#[derive(Clone, Serialize)]
+pub struct SearchStats {
+    pub nodes: u64,
+    pub tt_probes: u64,
+    pub tt_hits: u64,
+    pub cutoffs: Vec<u64>,
+    pub expanded: u64,
+    pub children: u64,
+    pub depth: i32,
+    pub max_ply: i32,
+    pub time_ms: f64,
+}
+
+pub fn new_stats(depth: i32) -> SearchStats {
+    SearchStats {
+        nodes: 0,
+        tt_probes: 0,
+        tt_hits: 0,
+        cutoffs: Vec::new(),
+        expanded: 0,
+        children: 0,
+        depth,
+        max_ply: 0,
+        time_ms: 0.0,
+    }
+}
+
+pub fn node(stats: &mut SearchStats, ply: i32) {
+    stats.nodes += 1;
+    stats.max_ply = stats.max_ply.max(ply);
+}
+
+pub fn tt_probe(stats: &mut SearchStats, hit: bool) {
+    stats.tt_probes += 1;
+    if hit {
+        stats.tt_hits += 1;
+    }
+}
+
+pub fn expanded(stats: &mut SearchStats, children: usize) {
+    stats.expanded += 1;
+    stats.children += children as u64;
+}
+
+pub fn cutoff(stats: &mut SearchStats, index: usize) {
+    if stats.cutoffs.len() <= index {
+        stats.cutoffs.resize(index + 1, 0);
+    }
+    stats.cutoffs[index] += 1;
+}
+
+// Records how long the search took, from start to now
+pub fn finish(stats: &mut SearchStats, start: Instant) {
+    stats.time_ms = start.elapsed().as_secs_f64() * 1000.0;
+}
+
+pub fn nodes_per_second(stats: &SearchStats) -> f64 {
+    if stats.time_ms <= 0.0 {
+        return 0.0;
+    }
+    return stats.nodes as f64 / (stats.time_ms / 1000.0);
+}
+
+pub fn tt_hit_rate(stats: &SearchStats) -> f64 {
+    return ratio(stats.tt_hits, stats.tt_probes);
+}
+
+pub fn branching_factor(stats: &SearchStats) -> f64 {
+    return ratio(stats.children, stats.expanded);
+}
+
+// Share of the cutoffs the first move tried made
+pub fn first_move_cutoff_rate(stats: &SearchStats) -> f64 {
+    let total: u64 = stats.cutoffs.iter().sum();
+    return ratio(stats.cutoffs.first().copied().unwrap_or(0), total);
+}
+
+fn ratio(part: u64, whole: u64) -> f64 {
+    if whole == 0 {
+        return 0.0;
+    }
+    return part as f64 / whole as f64;
+}
+
+// Adds other's counts to total, for totals over several searches
+pub fn add(total: &mut SearchStats, other: &SearchStats) {
+    total.nodes += other.nodes;
+    total.tt_probes += other.tt_probes;
+    total.tt_hits += other.tt_hits;
+    if total.cutoffs.len() < other.cutoffs.len() {
+        total.cutoffs.resize(other.cutoffs.len(), 0);
+    }
+    for (i, count) in other.cutoffs.iter().enumerate() {
+        total.cutoffs[i] += count;
+    }
+    total.expanded += other.expanded;
+    total.children += other.children;
+    total.depth = total.depth.max(other.depth);
+    total.max_ply = total.max_ply.max(other.max_ply);
+    total.time_ms += other.time_ms;
+}
+
+// One line, for tables
+pub fn summary(stats: &SearchStats) -> String {
+    return format!(
+        "{:>10} nodes {:>9.1} ms {:>10.0} nps  tt {:>5.1}%  first cut {:>5.1}%  bf {:>4.2}  depth {}/{}",
+        stats.nodes,
+        stats.time_ms,
+        nodes_per_second(stats),
+        tt_hit_rate(stats) * 100.0,
+        first_move_cutoff_rate(stats) * 100.0,
+        branching_factor(stats),
+        stats.depth,
+        stats.max_ply
+    );
+}
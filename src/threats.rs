@@ -0,0 +1,339 @@
+// Threat analysis, after Victor Allis's knowledge-based approach to
+// Connect 4 ("A Knowledge-based Approach of Connect-Four", 1988).
+//
+// A threat is an empty cell that would complete a line for a player. In a
+// column whatever is below a threat has to fill up before anyone can play
+// it, so threats decide games long before they are playable. Rows are
+// counted from the bottom, starting at 1: the first player (White in
+// Allis's terms, the one to move when an even number of cells are left)
+// ends up with the odd rows and the second player (Black) with the even
+// ones when the board fills up, which is what zugzwang control is about.
+//
+// The rules are the ways the player answering the moves (the defender) can
+// make sure of getting cells, each stopping the lines through them:
+//   claimeven   - the lower cell of a pair is playable and odd: answer the
+//                 opponent there by playing the even cell on top
+//   baseinverse - two playable cells: when the opponent takes one, take
+//                 the other, so a line needing both never happens
+//   vertical    - two cells on top of each other with the upper one odd:
+//                 answer the lower one with the upper one
+// Only applications that stop at least one of the opponent's lines are
+// listed. Everything here only makes sense for two players on a gravity
+// board without pops or obstacles, where the board fills up row by row;
+// other boards get their threats but nothing else. Cells sealed in under
+// an obstacle can never be played, so they are never threats.
+use crate::board;
+use crate::board::Board;
+
+// How many rule applications the hint display lists
+const HINT_RULES: usize = 6;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Rule {
+    Claimeven,
+    Baseinverse,
+    Vertical,
+}
+
+pub struct Threat {
+    pub piece: usize,
+    pub row: usize,
+    pub column: usize,
+    // Odd row counted from the bottom
+    pub odd: bool,
+    // Can be played right now, which means it's a win in one
+    pub playable: bool,
+}
+
+pub struct Application {
+    pub rule: Rule,
+    // The two cells the rule is about, (row, column), lower one first
+    pub cells: [(usize, usize); 2],
+    // How many of the opponent's possible lines it stops
+    pub refutes: usize,
+}
+
+pub struct Analysis {
+    pub threats: Vec<Threat>,
+    // The piece that controls zugzwang, None where it doesn't apply
+    pub zugzwang: Option<usize>,
+    // The player who isn't to move, who the rules are applied for
+    pub defender: usize,
+    pub applications: Vec<Application>,
+    // Whether zugzwang and the rules mean anything on this board at all
+    pub applicable: bool,
+}
+
+pub fn analyze(board: &Board) -> Analysis {
+    let threats = find_threats(board);
+    let defender = board::next_player(board, board::next_piece(board));
+    let mut analysis = Analysis {
+        zugzwang: None,
+        defender,
+        applications: Vec::new(),
+        threats,
+        applicable: false,
+    };
+    if board::players(board) != 2
+        || !board::has_gravity(board)
+        || board::is_popout(board)
+        || board::is_misere(board)
+        || board::has_obstacles(board)
+    {
+        return analysis;
+    }
+    analysis.applicable = true;
+    analysis.zugzwang = zugzwang_control(board, &analysis.threats);
+    analysis.applications = rule_applications(board);
+    return analysis;
+}
+
+// Row number counted from the bottom, starting at 1. Gravity-free boards
+// count from the top, like their row labels.
+pub fn row_number(board: &Board, row: usize) -> usize {
+    if !board::has_gravity(board) {
+        return row + 1;
+    }
+    return board::get_grid(board).len() - row;
+}
+
+fn is_odd(board: &Board, row: usize) -> bool {
+    return row_number(board, row) % 2 == 1;
+}
+
+// The row a piece dropped in the column would land on
+fn playable_row(board: &Board, col: usize) -> Option<usize> {
+    if !board::has_gravity(board) {
+        return None;
+    }
+    return board.lowest_empty[col].checked_sub(1);
+}
+
+fn find_threats(board: &Board) -> Vec<Threat> {
+    let grid = board::get_grid(board);
+    let mut threats = Vec::new();
+    for (row, cells) in grid.iter().enumerate() {
+        for (column, cell) in cells.iter().enumerate() {
+            // Empty cells below the top piece of a column are walled in
+            // by an obstacle
+            if *cell != 0 || (board::has_gravity(board) && row >= board.lowest_empty[column]) {
+                continue;
+            }
+            let playable = !board::has_gravity(board) || playable_row(board, column) == Some(row);
+            for piece in 1..=board::players(board) {
+                if board::completes_line(board, row, column, piece) {
+                    threats.push(Threat {
+                        piece,
+                        row,
+                        column,
+                        odd: is_odd(board, row),
+                        playable,
+                    });
+                }
+            }
+        }
+    }
+    return threats;
+}
+
+// Allis's simplified rules: White controls zugzwang with an odd threat
+// that Black has no threat underneath, otherwise Black controls it by
+// claimeven, taking every even cell as the board fills up. That needs the
+// columns to pair up, so it's only worked out for an even number of rows.
+fn zugzwang_control(board: &Board, threats: &[Threat]) -> Option<usize> {
+    if !board::get_grid(board).len().is_multiple_of(2) {
+        return None;
+    }
+    let to_move = board::next_piece(board);
+    let white = if board::empty_cells(board).is_multiple_of(2) {
+        to_move
+    } else {
+        board::next_player(board, to_move)
+    };
+    let black = board::next_player(board, white);
+    let white_odd = threats.iter().filter(|t| t.piece == white && t.odd);
+    for threat in white_odd {
+        let blocked = threats
+            .iter()
+            .any(|t| t.piece == black && t.column == threat.column && t.row > threat.row);
+        if !blocked {
+            return Some(white);
+        }
+    }
+    return Some(black);
+}
+
+#[allow(clippy::needless_range_loop)]
+fn rule_applications(board: &Board) -> Vec<Application> {
+    let grid = board::get_grid(board);
+    let height = grid.len();
+    let width = board::width(board);
+    let mut candidates = Vec::new();
+
+    // Claimeven: pairs from the playable cell up, as long as that is odd.
+    // Once a pair fills up the next one is playable, so they stack.
+    for col in 0..width {
+        if let Some(bottom) = playable_row(board, col) {
+            if !is_odd(board, bottom) {
+                continue;
+            }
+            let mut row = bottom;
+            while row >= 1 && grid[row][col] == 0 && grid[row - 1][col] == 0 {
+                candidates.push((Rule::Claimeven, [(row, col), (row - 1, col)]));
+                if row < 2 {
+                    break;
+                }
+                row -= 2;
+            }
+        }
+    }
+
+    // Baseinverse: any two playable cells
+    let playable: Vec<(usize, usize)> = (0..width)
+        .filter_map(|col| playable_row(board, col).map(|row| (row, col)))
+        .collect();
+    for (i, first) in playable.iter().enumerate() {
+        for second in playable[i + 1..].iter() {
+            candidates.push((Rule::Baseinverse, [*first, *second]));
+        }
+    }
+
+    // Vertical: two empty cells on top of each other, the upper one odd
+    for col in 0..width {
+        for row in 1..height {
+            if grid[row][col] == 0 && grid[row - 1][col] == 0 && is_odd(board, row - 1) {
+                candidates.push((Rule::Vertical, [(row, col), (row - 1, col)]));
+            }
+        }
+    }
+
+    // The opponent's possible lines: nothing in them but their own pieces
+    let attacker = board::next_piece(board);
+    let mut lines: Vec<Vec<(usize, usize)>> = Vec::new();
+    board::for_each_line(board, |line| {
+        if line
+            .iter()
+            .all(|(row, col)| grid[*row][*col] == 0 || grid[*row][*col] == attacker)
+        {
+            lines.push(line.to_vec());
+        }
+    });
+
+    let mut applications = Vec::new();
+    for (rule, cells) in candidates {
+        let refutes = lines
+            .iter()
+            .filter(|line| match rule {
+                // The defender gets the even cell whatever happens below
+                Rule::Claimeven => line.contains(&cells[1]),
+                _ => line.contains(&cells[0]) && line.contains(&cells[1]),
+            })
+            .count();
+        if refutes > 0 {
+            applications.push(Application {
+                rule,
+                cells,
+                refutes,
+            });
+        }
+    }
+    return applications;
+}
+
+pub fn rule_name(rule: Rule) -> &'static str {
+    match rule {
+        Rule::Claimeven => return "claimeven",
+        Rule::Baseinverse => return "baseinverse",
+        Rule::Vertical => return "vertical",
+    }
+}
+
+// The hint display: threats, zugzwang and the rules, in words
+pub fn describe(board: &Board, analysis: &Analysis) -> String {
+    let symbol = |piece: usize| board::SYMBOLS[piece - 1];
+    let cell =
+        |(row, col): (usize, usize)| format!("column {} row {}", col + 1, row_number(board, row));
+    let mut out = String::new();
+
+    out.push_str("Threats:\n");
+    for piece in 1..=board::players(board) {
+        let own: Vec<String> = analysis
+            .threats
+            .iter()
+            .filter(|t| t.piece == piece)
+            .map(|t| {
+                let mut text = cell((t.row, t.column));
+                if board::has_gravity(board) {
+                    text.push_str(if t.odd { " (odd)" } else { " (even)" });
+                }
+                if t.playable {
+                    text.push_str(" - wins now");
+                }
+                return text;
+            })
+            .collect();
+        if own.is_empty() {
+            out.push_str(&format!("  {}: none\n", symbol(piece)));
+        } else {
+            out.push_str(&format!("  {}: {}\n", symbol(piece), own.join(", ")));
+        }
+    }
+
+    if !analysis.applicable {
+        out.push_str("Zugzwang and rules: not applicable on this board\n");
+    }
+    if let Some(piece) = analysis.zugzwang {
+        out.push_str(&format!("Zugzwang: {} controls it\n", symbol(piece)));
+    }
+    if !analysis.applications.is_empty() {
+        // The ones that stop the most lines first, there can be dozens
+        let mut applications: Vec<&Application> = analysis.applications.iter().collect();
+        applications.sort_by_key(|a| std::cmp::Reverse(a.refutes));
+        out.push_str(&format!(
+            "Rules {} can answer with:\n",
+            symbol(analysis.defender)
+        ));
+        for application in applications.iter().take(HINT_RULES) {
+            out.push_str(&format!(
+                "  {} {} and {}, stops {} line{}\n",
+                rule_name(application.rule),
+                cell(application.cells[0]),
+                cell(application.cells[1]),
+                application.refutes,
+                if application.refutes == 1 { "" } else { "s" }
+            ));
+        }
+        if applications.len() > HINT_RULES {
+            out.push_str(&format!("  and {} more\n", applications.len() - HINT_RULES));
+        }
+    }
+    return out;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cells_under_an_obstacle_are_not_threats() {
+        let board =
+            board::from_map(".......\n.......\n.......\n.......\n...#...\nXXX....\n").unwrap();
+        let analysis = analyze(&board);
+        assert!(!analysis.threats.iter().any(|t| (t.row, t.column) == (5, 3)));
+        assert!(!analysis.applicable);
+        assert_eq!(analysis.zugzwang, None);
+        assert!(analysis.applications.is_empty());
+    }
+
+    #[test]
+    fn open_cells_are_threats() {
+        let board =
+            board::from_map(".......\n.......\n.......\n.......\n.......\nXXX.OO.\n").unwrap();
+        let analysis = analyze(&board);
+        assert!(analysis
+            .threats
+            .iter()
+            .any(|t| t.piece == 1 && (t.row, t.column) == (5, 3) && t.playable));
+        assert!(analysis.applicable);
+    }
+}
@@ -0,0 +1,150 @@
+// Puzzle trainer: the human plays the winning side of each puzzle from
+// puzzle.rs and the computer defends, with the longest-lasting defense from
+// the puzzle's solution tree. A move that isn't in the tree doesn't win in
+// time, so the attempt is over and the puzzle starts again.
+//
+// A solved puzzle is worth FULL_POINTS, less one for every hint and retry.
+// The first hint for a move shows the threats in the position, the second
+// gives the move away.
+use crate::board;
+use crate::board::{Board, Move};
+use crate::player;
+use crate::player::HumanInput;
+use crate::puzzle::{Attack, Puzzle};
+use crate::rules;
+use crate::rules::Rules;
+use crate::threats;
+use rand::{seq::SliceRandom, thread_rng};
+
+const FULL_POINTS: usize = 3;
+const COMMANDS: [&str; 4] = ["hint", "retry", "skip", "quit"];
+
+enum Outcome {
+    // Points scored
+    Solved(usize),
+    Skipped,
+    Quit,
+}
+
+// Plays puzzles from number first (counting from 1) to the end
+pub fn train(puzzles: &[Puzzle], first: usize, width: usize, height: usize) {
+    println!("Play the winning side. Type a column, or hint, retry, skip or quit.");
+    let mut played = 0;
+    let mut solved = 0;
+    let mut points = 0;
+    for (i, puzzle) in puzzles.iter().enumerate().skip(first - 1) {
+        let start = match board::from_move_string(&puzzle.moves, width, height) {
+            Ok(start) => start,
+            Err(e) => {
+                println!("Skipping puzzle {}: {}", i + 1, e);
+                continue;
+            }
+        };
+        println!();
+        println!(
+            "Puzzle {} of {}: {} to move and win in {}",
+            i + 1,
+            puzzles.len(),
+            board::SYMBOLS[puzzle.to_move - 1],
+            puzzle.win_in
+        );
+        match play_puzzle(puzzle, &start) {
+            Outcome::Solved(score) => {
+                played += 1;
+                solved += 1;
+                points += score;
+                println!("Solved! {} of {} points", score, FULL_POINTS);
+            }
+            Outcome::Skipped => {
+                played += 1;
+                println!("Solution: {}", main_line(&puzzle.solution));
+            }
+            Outcome::Quit => break,
+        }
+    }
+    println!();
+    println!(
+        "Solved {} of {} puzzles, {} of {} points",
+        solved,
+        played,
+        points,
+        played * FULL_POINTS
+    );
+}
+
+fn play_puzzle(puzzle: &Puzzle, start: &Board) -> Outcome {
+    let mut rng = thread_rng();
+    let mut cost = 0;
+    'attempt: loop {
+        let mut game = rules::connect(board::clone_board(start));
+        let mut node = &puzzle.solution;
+        let mut moves_left = puzzle.win_in;
+        let mut hints = 0;
+        print!("{}", game.render());
+        loop {
+            println!("Win in {}:", moves_left);
+            let column = match player::get_human_input(&game, &COMMANDS) {
+                HumanInput::Move(Move::Drop(column)) => column + 1,
+                HumanInput::Move(_) => continue,
+                HumanInput::Command(command) => match command.as_str() {
+                    "hint" => {
+                        cost += 1;
+                        hints += 1;
+                        if hints == 1 {
+                            let board = game.board().unwrap();
+                            print!("{}", threats::describe(board, &threats::analyze(board)));
+                        } else if let Some(attack) = node.moves.first() {
+                            println!("Try column {}", attack.column);
+                        }
+                        continue;
+                    }
+                    "retry" => {
+                        cost += 1;
+                        continue 'attempt;
+                    }
+                    "skip" => return Outcome::Skipped,
+                    _ => return Outcome::Quit,
+                },
+            };
+
+            let attack = match node.moves.iter().find(|m| m.column == column) {
+                Some(attack) => attack,
+                None => {
+                    println!("Column {} doesn't win in {}, try again", column, moves_left);
+                    cost += 1;
+                    continue 'attempt;
+                }
+            };
+            game.apply(Move::Drop(column - 1));
+            if attack.defenses.is_empty() {
+                print!("{}", game.render());
+                return Outcome::Solved(FULL_POINTS.saturating_sub(cost));
+            }
+            let defense = attack.defenses.choose(&mut rng).unwrap();
+            game.apply(Move::Drop(defense.column - 1));
+            println!("Defended in column {}", defense.column);
+            print!("{}", game.render());
+            node = &defense.then;
+            moves_left -= 1;
+            hints = 0;
+        }
+    }
+}
+
+// The first winning move and defense all the way down, the defenses in
+// brackets, like "5 (4) 3 (3) 2"
+fn main_line(attack: &Attack) -> String {
+    let mut line = Vec::new();
+    let mut node = attack;
+    while let Some(attack_move) = node.moves.first() {
+        line.push(attack_move.column.to_string());
+        match attack_move.defenses.first() {
+            Some(defense) => {
+                line.push(format!("({})", defense.column));
+                node = &defense.then;
+            }
+            None => break,
+        }
+    }
+    return line.join(" ");
+}
@@ -0,0 +1,318 @@
+// game_over_check only looks around the last move and walks each direction
+// with bounds worked out by hand, so it's checked here against the dumbest
+// possible win check: try every line of connect cells on the whole board.
+//
+// Random games cover every board size with and without gravity, cylinders,
+// more players and blocked cells, small boards are checked in every
+// reachable position, and proptest looks for move sequences that disagree.
+// The big run is ignored by default:
+//
+//   cargo test --release --test win_detection -- --ignored
+//
+// plays a million games on the standard board (WIN_CHECK_GAMES to change).
+#![allow(clippy::needless_return)]
+use proptest::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rust4::board;
+use rust4::board::{Board, Move};
+use std::collections::HashSet;
+use std::env;
+
+// Every player with connect of their pieces in a straight line anywhere,
+// in order. With wrap lines carry on from the last column to the first.
+fn reference_winners(
+    grid: &[Vec<usize>],
+    connect: usize,
+    wrap: bool,
+    players: usize,
+) -> Vec<usize> {
+    let height = grid.len() as isize;
+    let width = grid[0].len() as isize;
+    let mut found = Vec::new();
+    for row in 0..height {
+        for col in 0..width {
+            for (dr, dc) in [(0, 1), (1, 0), (1, 1), (-1, 1)] {
+                let mut cells = Vec::new();
+                for i in 0..connect as isize {
+                    let r = row + dr * i;
+                    let mut c = col + dc * i;
+                    if wrap {
+                        c = c.rem_euclid(width);
+                    }
+                    if r < 0 || r >= height || c < 0 || c >= width {
+                        break;
+                    }
+                    cells.push(grid[r as usize][c as usize]);
+                }
+                if cells.len() < connect {
+                    continue;
+                }
+                let piece = cells[0];
+                if piece >= 1 && piece <= players && cells.iter().all(|cell| *cell == piece) {
+                    found.push(piece);
+                }
+            }
+        }
+    }
+    found.sort();
+    found.dedup();
+    return found;
+}
+
+// How a test board was made, which the board itself doesn't say
+struct Setup {
+    connect: usize,
+    wrap: bool,
+    players: usize,
+}
+
+// An empty board with gravity, built through a map so any size, line length
+// and number of players works. Cells in blocked are BLOCKED.
+fn gravity_board(width: usize, height: usize, setup: &Setup, blocked: &[(usize, usize)]) -> Board {
+    let mut text = format!("connect {}\nplayers {}\n", setup.connect, setup.players);
+    for row in 0..height {
+        for col in 0..width {
+            text.push(if blocked.contains(&(row, col)) {
+                '#'
+            } else {
+                '.'
+            });
+        }
+        text.push('\n');
+    }
+    let mut board = board::from_map(&text).unwrap();
+    board::set_print(&mut board, false);
+    return board;
+}
+
+// Plays mv and checks game_over_check and board::winners against the
+// reference. No one had a line before the move, so the only possible
+// winner is whoever just moved. Returns whether the game is over.
+fn play_and_check(board: &mut Board, mv: Move, setup: &Setup) -> bool {
+    let piece = board::next_piece(board);
+    assert!(board::make_move(board, mv, piece), "{:?} isn't legal", mv);
+    let expected = reference_winners(
+        board::get_grid(board),
+        setup.connect,
+        setup.wrap,
+        setup.players,
+    );
+    let over = board::game_over_check(board);
+    let context = || {
+        format!(
+            "after {:?} by {} (connect {}, wrap {})\n{}",
+            mv,
+            piece,
+            setup.connect,
+            setup.wrap,
+            board::board_to_string(board)
+        )
+    };
+    assert_eq!(over, !expected.is_empty(), "game_over_check {}", context());
+    assert!(
+        expected.is_empty() || expected == vec![piece],
+        "someone else won {}",
+        context()
+    );
+    let mut winners = board::winners(board);
+    winners.sort();
+    assert_eq!(winners, expected, "winners {}", context());
+    return over;
+}
+
+// Random moves until someone wins or the board fills up
+fn random_game(board: &mut Board, setup: &Setup, rng: &mut StdRng) {
+    loop {
+        let moves = board::legal_moves(board);
+        if moves.is_empty() {
+            return;
+        }
+        let mv = moves[rng.gen_range(0..moves.len())];
+        if play_and_check(board, mv, setup) {
+            return;
+        }
+    }
+}
+
+#[test]
+fn random_games_on_every_board_size() {
+    let mut rng = StdRng::seed_from_u64(1);
+    for width in 1..=9 {
+        for height in 1..=8 {
+            for connect in 2..=6 {
+                let setup = Setup {
+                    connect,
+                    wrap: false,
+                    players: 2,
+                };
+                for _ in 0..30 {
+                    let mut board = gravity_board(width, height, &setup, &[]);
+                    random_game(&mut board, &setup, &mut rng);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn random_games_on_cylinders() {
+    let mut rng = StdRng::seed_from_u64(2);
+    for width in 4..=9 {
+        for height in 1..=8 {
+            let setup = Setup {
+                connect: 4,
+                wrap: true,
+                players: 2,
+            };
+            for _ in 0..100 {
+                let mut board = board::new_cylinder_board(width, height);
+                board::set_print(&mut board, false);
+                random_game(&mut board, &setup, &mut rng);
+            }
+        }
+    }
+}
+
+#[test]
+fn random_games_without_gravity() {
+    let mut rng = StdRng::seed_from_u64(3);
+    for width in 1..=9 {
+        for height in 1..=9 {
+            for connect in 2..=6 {
+                let setup = Setup {
+                    connect,
+                    wrap: false,
+                    players: 2,
+                };
+                for _ in 0..20 {
+                    let mut board = board::new_mnk_board(width, height, connect);
+                    board::set_print(&mut board, false);
+                    random_game(&mut board, &setup, &mut rng);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn random_games_with_more_players() {
+    let mut rng = StdRng::seed_from_u64(4);
+    for players in 3..=board::SYMBOLS.len() {
+        for (width, height) in [(7, 6), (9, 7), (12, 10)] {
+            let setup = Setup {
+                connect: 4,
+                wrap: false,
+                players,
+            };
+            for _ in 0..100 {
+                let mut board = gravity_board(width, height, &setup, &[]);
+                random_game(&mut board, &setup, &mut rng);
+            }
+        }
+    }
+}
+
+#[test]
+fn random_games_with_blocked_cells() {
+    let mut rng = StdRng::seed_from_u64(5);
+    let setup = Setup {
+        connect: 4,
+        wrap: false,
+        players: 2,
+    };
+    for _ in 0..2000 {
+        let blocked: Vec<(usize, usize)> = (0..rng.gen_range(1..8))
+            .map(|_| (rng.gen_range(0..6), rng.gen_range(0..7)))
+            .collect();
+        let mut board = gravity_board(7, 6, &setup, &blocked);
+        random_game(&mut board, &setup, &mut rng);
+    }
+}
+
+// Every position that can come up on small boards, each move from each of
+// them checked once
+fn check_every_position(width: usize, height: usize, connect: usize, wrap: bool) {
+    let setup = Setup {
+        connect,
+        wrap,
+        players: 2,
+    };
+    let mut board = if wrap {
+        let mut board = board::new_cylinder_board(width, height);
+        board::set_print(&mut board, false);
+        board
+    } else {
+        gravity_board(width, height, &setup, &[])
+    };
+    let mut seen = HashSet::new();
+    visit(&mut board, &setup, &mut seen);
+    assert!(seen.len() > 1);
+}
+
+fn visit(board: &mut Board, setup: &Setup, seen: &mut HashSet<Vec<Vec<usize>>>) {
+    if !seen.insert(board::get_grid(board).clone()) {
+        return;
+    }
+    for mv in board::legal_moves(board) {
+        let over = play_and_check(board, mv, setup);
+        if !over {
+            visit(board, setup, seen);
+        }
+        board::undo_move(board);
+    }
+}
+
+#[test]
+fn every_position_on_small_boards() {
+    check_every_position(3, 3, 2, false);
+    check_every_position(3, 3, 3, false);
+    check_every_position(4, 4, 3, false);
+    check_every_position(4, 4, 4, false);
+    check_every_position(4, 4, 4, true);
+}
+
+#[test]
+#[ignore]
+fn a_million_standard_games() {
+    let games: usize = env::var("WIN_CHECK_GAMES")
+        .ok()
+        .and_then(|games| games.parse().ok())
+        .unwrap_or(1_000_000);
+    let mut rng = StdRng::seed_from_u64(6);
+    let setup = Setup {
+        connect: 4,
+        wrap: false,
+        players: 2,
+    };
+    for _ in 0..games {
+        let mut board = board::new_board(7, 6);
+        board::set_print(&mut board, false);
+        random_game(&mut board, &setup, &mut rng);
+    }
+}
+
+proptest! {
+    // Columns are taken modulo the width, full columns skip to the next
+    // one that isn't
+    #[test]
+    fn any_move_sequence_agrees(
+        width in 1usize..=9,
+        height in 1usize..=8,
+        connect in 2usize..=6,
+        columns in prop::collection::vec(0usize..9, 0..80),
+    ) {
+        let setup = Setup { connect, wrap: false, players: 2 };
+        let mut board = gravity_board(width, height, &setup, &[]);
+        for column in columns {
+            let open = board::get_empty_columns(&board);
+            if open.is_empty() {
+                break;
+            }
+            let column = *open.iter().find(|c| **c >= column % width).unwrap_or(&open[0]);
+            if play_and_check(&mut board, Move::Drop(column), &setup) {
+                break;
+            }
+        }
+    }
+}